@@ -38,19 +38,13 @@ fn main() -> ! {
         let (mut tx, mut _rx) = serial.split();
 
         /* Initialise I2C pins */
-        let scl = gpioa
-            .pa9
-            .into_alternate_af4()
-            .internal_pull_up(true)
-            .set_open_drain();
-        let sda = gpioa
-            .pa10
-            .into_alternate_af4()
-            .internal_pull_up(true)
-            .set_open_drain();
+        let mut scl = gpioa.pa9.into_alternate_af4().set_open_drain();
+        scl.internal_pull_up(true);
+        let mut sda = gpioa.pa10.into_alternate_af4().set_open_drain();
+        sda.internal_pull_up(true);
 
         /* Setup I2C1 */
-        let mut i2c = I2c::i2c1(p.I2C1, (scl, sda), 1.khz());
+        let mut i2c = I2c::i2c1(p.I2C1, (scl, sda), 1.khz(), clocks);
         let mut ina260 = INA260::new(i2c, 0x40).unwrap();
 
         /* Endless loop */
@@ -41,21 +41,15 @@ fn main() -> ! {
     if let Some(p) = stm32::Peripherals::take() {
         let gpioa = p.GPIOA.split();
         let mut rcc = p.RCC.constrain();
-        let _ = rcc.cfgr.freeze();
+        let clocks = rcc.cfgr.freeze();
 
-        let scl = gpioa
-            .pa9
-            .into_alternate_af4()
-            .internal_pull_up(true)
-            .set_open_drain();
-        let sda = gpioa
-            .pa10
-            .into_alternate_af4()
-            .internal_pull_up(true)
-            .set_open_drain();
+        let mut scl = gpioa.pa9.into_alternate_af4().set_open_drain();
+        scl.internal_pull_up(true);
+        let mut sda = gpioa.pa10.into_alternate_af4().set_open_drain();
+        sda.internal_pull_up(true);
 
         /* Setup I2C1 */
-        let mut i2c = I2c::i2c1(p.I2C1, (scl, sda), 400.khz());
+        let mut i2c = I2c::i2c1(p.I2C1, (scl, sda), 400.khz(), clocks);
 
         /* Initialise SSD1306 display */
         let _ = ssd1306_init(&mut i2c);
@@ -0,0 +1,57 @@
+#![no_main]
+#![no_std]
+
+extern crate cortex_m;
+extern crate cortex_m_rt;
+extern crate panic_halt;
+
+extern crate stm32f030_hal as hal;
+
+use hal::delay::Delay;
+use hal::prelude::*;
+use hal::pwm::Pwm;
+use hal::stm32;
+
+use cortex_m::peripheral::Peripherals;
+use cortex_m_rt::entry;
+
+#[entry]
+fn main() -> ! {
+    if let (Some(p), Some(cp)) = (stm32::Peripherals::take(), Peripherals::take()) {
+        let gpioa = p.GPIOA.split();
+
+        /* Constrain clocking registers */
+        let rcc = p.RCC.constrain();
+
+        /* Configure clock to 48 MHz and freeze it */
+        let clocks = rcc.cfgr.sysclk(48.mhz()).freeze();
+
+        /* Use PA6 and PA7 as TIM3 channel 1 and 2 outputs */
+        let pins = (
+            gpioa.pa6.into_alternate_af1(),
+            gpioa.pa7.into_alternate_af1(),
+        );
+
+        /* Run the PWM at 1 kHz and split it into its channels */
+        let ((mut c1, mut c2), _pwm) = Pwm::tim3(p.TIM3, pins, 1.khz(), clocks).split();
+        let max = c1.get_max_duty();
+        c1.enable();
+        c2.enable();
+
+        /* Get delay provider */
+        let mut delay = Delay::new(cp.SYST, clocks);
+
+        loop {
+            /* Fade the LEDs in opposite directions */
+            for i in 0..=100 {
+                c1.set_duty(max / 100 * i);
+                c2.set_duty(max - max / 100 * i);
+                delay.delay_ms(10_u16);
+            }
+        }
+    }
+
+    loop {
+        continue;
+    }
+}
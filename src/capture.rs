@@ -0,0 +1,407 @@
+use stm32::{TIM1, TIM14, TIM15, TIM16, TIM17, TIM3};
+
+use cast::u32;
+use hal;
+use nb;
+use rcc::Clocks;
+
+use pwm::{Channel, PinC1};
+use time::Hertz;
+
+/// Input capture abstraction for a whole timer
+pub struct Capture<TIM, PINS> {
+    tim: TIM,
+    pins: PINS,
+    clocks: Clocks,
+}
+
+/// PWM input measuring period and high time of the signal on channel 1
+pub struct PwmInput<TIM, PIN> {
+    tim: TIM,
+    pin: PIN,
+    clocks: Clocks,
+}
+
+/// Set of channel input pins of a timer, `CHANNELS` is a tuple of the used channels
+pub trait InputPins<TIM, CHANNELS> {
+    const C1: bool = false;
+    const C2: bool = false;
+    const C3: bool = false;
+    const C4: bool = false;
+}
+
+/// Input capture error
+#[derive(Debug)]
+pub enum Error {
+    /// A capture was overwritten before it was read
+    Overcapture,
+    /// The period of the signal is longer than the counter range
+    Overflow,
+}
+
+/// Signal edges that trigger a capture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Number of edges needed for a capture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prescaler {
+    Div1 = 0b00,
+    Div2 = 0b01,
+    Div4 = 0b10,
+    Div8 = 0b11,
+}
+
+/// A single measurement of a PWM input
+#[derive(Clone, Copy)]
+pub struct PwmReading {
+    /// Period in timer ticks
+    pub period: u16,
+    /// High time in timer ticks
+    pub duty: u16,
+    /// Frequency of the signal
+    pub frequency: Hertz,
+}
+
+/// Returns the prescaler for a counter running at `resolution` with the timer clock `clk`
+///
+/// The resolution can't be higher than the timer clock or lower than 1/65536th of it.
+fn prescaler(clk: Hertz, resolution: Hertz) -> u16 {
+    assert!(
+        resolution.0 > 0 && resolution.0 <= clk.0,
+        "resolution is higher than the timer clock"
+    );
+    let psc = clk.0 / resolution.0 - 1;
+    assert!(psc <= 0xFFFF, "resolution is below 1/65536th of the timer clock");
+
+    psc as u16
+}
+
+macro_rules! capture {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident) [
+        $($C:ident: ($ch:expr, $ccrX:ident, $ccmrX_output:ident),)+
+    ],)+) => {
+        $(
+            impl<PINS> Capture<$TIM, PINS> {
+                /// Configures the timer for input capture with a counter running at `resolution`
+                /// on the channels of `pins`
+                ///
+                /// All channels start disabled, capturing every rising edge without filter.
+                pub fn $tim<CHANNELS, R>(tim: $TIM, pins: PINS, resolution: R, clocks: Clocks) -> Self
+                where
+                    PINS: InputPins<$TIM, CHANNELS>,
+                    R: Into<Hertz>,
+                {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    let mut capture = Capture { tim, pins, clocks };
+                    $(
+                        if PINS::$C {
+                            capture.configure(Channel::$C, Edge::Rising, Prescaler::Div1, 0);
+                        }
+                    )+
+                    hal::Capture::set_resolution(&mut capture, resolution);
+
+                    /* Count through the full range */
+                    capture.tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    capture.tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    capture
+                }
+
+                /// Configures `channel` to capture on `edge`
+                ///
+                /// With `prescaler` only every 2nd, 4th or 8th edge is captured. `filter` is the
+                /// value of the ICxF field (0 to 15), which sets how many samples at which rate
+                /// the input has to be stable to be valid. The channel must be disabled.
+                #[allow(unreachable_patterns)]
+                pub fn configure(
+                    &mut self,
+                    channel: Channel,
+                    edge: Edge,
+                    prescaler: Prescaler,
+                    filter: u8,
+                ) {
+                    assert!(filter <= 15);
+
+                    /* Capture from the channel's own input. The PAC only describes the output
+                     * compare layout of CCMRx, `ccmrX_output` is the same register written with
+                     * the input capture bits. */
+                    let ccmr = 0b01 | (prescaler as u32) << 2 | u32(filter) << 4;
+                    let ccer = match edge {
+                        Edge::Rising => 0b0000,
+                        Edge::Falling => 0b0010,
+                        Edge::Both => 0b1010,
+                    };
+
+                    match channel {
+                        $(
+                            Channel::$C => {
+                                let offset = 8 * (($ch - 1) % 2);
+                                self.tim.$ccmrX_output.modify(|r, w| unsafe {
+                                    w.bits((r.bits() & !(0xFF << offset)) | (ccmr << offset))
+                                });
+
+                                let offset = 4 * ($ch - 1);
+                                self.tim.ccer.modify(|r, w| unsafe {
+                                    w.bits((r.bits() & !(0b1010 << offset)) | (ccer << offset))
+                                });
+                            }
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                /// Stops the timer and releases the TIM peripheral and pins
+                pub fn release(self) -> ($TIM, PINS) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pins)
+                }
+            }
+
+            impl<PINS> hal::Capture for Capture<$TIM, PINS> {
+                type Error = Error;
+                type Channel = Channel;
+                type Time = Hertz;
+                type Capture = u16;
+
+                /// Returns the counter value at the last capture of `channel`
+                ///
+                /// An overcapture is reported once, the following call returns the latest capture.
+                #[allow(unreachable_patterns)]
+                fn capture(&mut self, channel: Channel) -> nb::Result<u16, Error> {
+                    let sr = self.tim.sr.read().bits();
+
+                    match channel {
+                        $(
+                            Channel::$C => {
+                                if sr & (1 << ($ch + 8)) != 0 {
+                                    /* The flags are cleared by writing 0, writing 1 has no effect */
+                                    self.tim.sr.write(|w| unsafe {
+                                        w.bits(0xFFFF & !(1 << ($ch + 8)))
+                                    });
+                                    Err(nb::Error::Other(Error::Overcapture))
+                                } else if sr & (1 << $ch) != 0 {
+                                    /* Reading the capture clears CCxIF */
+                                    Ok(self.tim.$ccrX.read().bits() as u16)
+                                } else {
+                                    Err(nb::Error::WouldBlock)
+                                }
+                            }
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                #[allow(unreachable_patterns)]
+                fn disable(&mut self, channel: Channel) {
+                    match channel {
+                        $(
+                            Channel::$C => {
+                                self.tim.ccer.modify(|r, w| unsafe {
+                                    w.bits(r.bits() & !(1 << (4 * ($ch - 1))))
+                                });
+                            }
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                #[allow(unreachable_patterns)]
+                fn enable(&mut self, channel: Channel) {
+                    match channel {
+                        $(
+                            Channel::$C => {
+                                self.tim.ccer.modify(|r, w| unsafe {
+                                    w.bits(r.bits() | 1 << (4 * ($ch - 1)))
+                                });
+                            }
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                /// Returns the rate the counter is running at
+                fn get_resolution(&self) -> Hertz {
+                    let psc = self.tim.psc.read().bits() + 1;
+                    Hertz(self.clocks.timclk().0 / psc)
+                }
+
+                fn set_resolution<R>(&mut self, resolution: R)
+                where
+                    R: Into<Hertz>,
+                {
+                    let psc = prescaler(self.clocks.timclk(), resolution.into());
+                    self.tim.psc.write(|w| unsafe { w.bits(u32(psc)) });
+
+                    /* Load the new value right away */
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! pwm_input {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl<PIN> PwmInput<$TIM, PIN> {
+                /// Configures the timer to measure the PWM signal on the channel 1 `pin` with a
+                /// counter running at `resolution`
+                pub fn $tim<R>(tim: $TIM, pin: PIN, resolution: R, clocks: Clocks) -> Self
+                where
+                    PIN: PinC1<$TIM>,
+                    R: Into<Hertz>,
+                {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    /* Both channels capture TI1, channel 1 on rising and channel 2 on falling
+                     * edges. `ccmr1_output` is the only view of CCMR1 the PAC has, it's written
+                     * with the input capture bits. */
+                    tim.ccmr1_output.write(|w| unsafe { w.bits(0b01 | 0b10 << 8) });
+                    tim.ccer.write(|w| unsafe { w.bits(0x0001 | 0x0030) });
+
+                    /* Rising edges of TI1 reset the counter, so channel 1 captures the period and
+                     * channel 2 the high time */
+                    tim.smcr.write(|w| unsafe { w.bits(0b101 << 4 | 0b100) });
+
+                    let psc = prescaler(clocks.timclk(), resolution.into());
+                    tim.psc.write(|w| unsafe { w.bits(u32(psc)) });
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    tim.egr.write(|w| w.ug().set_bit());
+
+                    /* Only counter overflows set the update flag, not the resets */
+                    tim.sr.write(|w| unsafe { w.bits(0) });
+                    tim.cr1.modify(|_, w| w.urs().set_bit().cen().set_bit());
+
+                    PwmInput { tim, pin, clocks }
+                }
+
+                /// Returns the rate the counter is running at
+                pub fn resolution(&self) -> Hertz {
+                    let psc = self.tim.psc.read().bits() + 1;
+                    Hertz(self.clocks.timclk().0 / psc)
+                }
+
+                /// Returns the measurement of the last complete period
+                ///
+                /// Fails with `Error::Overflow` if there was no rising edge for a full counter
+                /// range, e.g. because the signal stopped, and with `Error::Overcapture` if a
+                /// period ended before the previous one was read. Both are reported once, the
+                /// following call returns the next complete period.
+                pub fn read(&mut self) -> nb::Result<PwmReading, Error> {
+                    let sr = self.tim.sr.read();
+
+                    if sr.cc1of().bit_is_set() || sr.cc2of().bit_is_set() {
+                        /* The captures may belong to different periods, drop them */
+                        self.tim.ccr1.read();
+                        self.tim.ccr2.read();
+                        self.tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0606) });
+                        Err(nb::Error::Other(Error::Overcapture))
+                    } else if sr.uif().bit_is_set() {
+                        /* The next capture only measured the end of the period, drop it */
+                        self.tim.ccr1.read();
+                        self.tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0201) });
+                        Err(nb::Error::Other(Error::Overflow))
+                    } else if sr.cc1if().bit_is_set() {
+                        /* The high time belongs to the period that just ended */
+                        let duty = self.tim.ccr2.read().bits() as u16;
+                        let period = self.tim.ccr1.read().bits() as u16;
+
+                        if period == 0 {
+                            return Err(nb::Error::WouldBlock);
+                        }
+
+                        Ok(PwmReading {
+                            period,
+                            duty,
+                            frequency: Hertz(self.resolution().0 / u32(period)),
+                        })
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Stops the timer and releases the TIM peripheral and pin
+                pub fn release(self) -> ($TIM, PIN) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+capture! {
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output),
+        C2: (2, ccr2, ccmr1_output),
+        C3: (3, ccr3, ccmr2_output),
+        C4: (4, ccr4, ccmr2_output),
+    ],
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr) [
+        C1: (1, ccr1, ccmr1_output),
+        C2: (2, ccr2, ccmr1_output),
+        C3: (3, ccr3, ccmr2_output),
+        C4: (4, ccr4, ccmr2_output),
+    ],
+    TIM14: (tim14, tim14en, tim14rst, apb1enr, apb1rstr) [
+        C1: (1, ccr1, ccmr1_output),
+    ],
+    TIM16: (tim16, tim16en, tim16rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output),
+    ],
+    TIM17: (tim17, tim17en, tim17rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output),
+    ],
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+pwm_input! {
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr),
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr),
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+capture! {
+    TIM15: (tim15, tim15en, tim15rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output),
+        C2: (2, ccr2, ccmr1_output),
+    ],
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+pwm_input! {
+    TIM15: (tim15, tim15en, tim15rst, apb2enr, apb2rstr),
+}
@@ -0,0 +1,219 @@
+//! Pulse counting with an external timer clock
+
+use stm32::{TIM1, TIM3};
+
+use gpio::*;
+use pwm::{PinC1, PinC2};
+
+/// Counter of the edges on an external input, extended to 64 bits in software
+///
+/// The counter overflows are counted by `on_overflow`, which has to be called from the timer
+/// interrupt or otherwise at least once per 65536 counts, `count` does so too.
+pub struct PulseCounter<TIM, INPUT> {
+    tim: TIM,
+    input: INPUT,
+    overflows: u64,
+}
+
+/// External trigger input pin
+pub trait EtrPin<TIM> {}
+
+/// External trigger input, counted in external clock mode 2
+pub struct Etr<PIN>(pub PIN);
+/// Channel 1 input, counted in external clock mode 1
+pub struct Ti1<PIN>(pub PIN);
+/// Channel 2 input, counted in external clock mode 1
+pub struct Ti2<PIN>(pub PIN);
+
+#[doc(hidden)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Source {
+    Etr,
+    Ti1,
+    Ti2,
+}
+
+/// Input a `PulseCounter` can count the edges of
+pub trait Input<TIM> {
+    #[doc(hidden)]
+    const SOURCE: Source;
+}
+
+impl<TIM, PIN: EtrPin<TIM>> Input<TIM> for Etr<PIN> {
+    const SOURCE: Source = Source::Etr;
+}
+
+impl<TIM, PIN: PinC1<TIM>> Input<TIM> for Ti1<PIN> {
+    const SOURCE: Source = Source::Ti1;
+}
+
+impl<TIM, PIN: PinC2<TIM>> Input<TIM> for Ti2<PIN> {
+    const SOURCE: Source = Source::Ti2;
+}
+
+/// Counted edge of the input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Polarity {
+    Rising,
+    Falling,
+}
+
+/// Pulse counter configuration
+#[derive(Clone, Copy)]
+pub struct Config {
+    polarity: Polarity,
+    prescaler: u16,
+    filter: u8,
+}
+
+impl Default for Config {
+    /// Counts every rising edge without filter
+    fn default() -> Self {
+        Config {
+            polarity: Polarity::Rising,
+            prescaler: 1,
+            filter: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Sets the counted edge
+    pub fn polarity(mut self, polarity: Polarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// Counts only once every `prescaler` edges
+    pub fn prescaler(mut self, prescaler: u16) -> Self {
+        assert!(prescaler > 0);
+        self.prescaler = prescaler;
+        self
+    }
+
+    /// Sets the input filter (0 to 15), which sets how many samples at which rate the input has
+    /// to be stable to be valid
+    pub fn filter(mut self, filter: u8) -> Self {
+        assert!(filter <= 15);
+        self.filter = filter;
+        self
+    }
+}
+
+#[cfg(any(
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl EtrPin<TIM1> for gpioa::PA12<Alternate<AF2>> {}
+
+// The TIM3 external trigger is on PD2, which isn't supported yet
+
+macro_rules! counter {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl<INPUT> PulseCounter<$TIM, INPUT> {
+                /// Configures the timer to count the edges on `input`
+                pub fn $tim(tim: $TIM, input: INPUT, config: Config) -> Self
+                where
+                    INPUT: Input<$TIM>,
+                {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    let falling = config.polarity == Polarity::Falling;
+                    match INPUT::SOURCE {
+                        Source::Etr => {
+                            tim.smcr.write(|w| unsafe {
+                                w.etp()
+                                    .bit(falling)
+                                    .etf()
+                                    .bits(config.filter)
+                                    .ece()
+                                    .set_bit()
+                            });
+                        }
+                        Source::Ti1 | Source::Ti2 => {
+                            /* The channel captures its own input with the filter, its polarity
+                             * selects the edge */
+                            let (offset, ts) = if INPUT::SOURCE == Source::Ti1 {
+                                (0, 0b101)
+                            } else {
+                                (8, 0b110)
+                            };
+                            tim.ccmr1_output.write(|w| unsafe {
+                                w.bits((0b01 | u32::from(config.filter) << 4) << offset)
+                            });
+                            tim.ccer.write(|w| unsafe {
+                                w.bits(if falling { 0b0010 << (offset / 2) } else { 0 })
+                            });
+                            tim.smcr.write(|w| unsafe { w.ts().bits(ts).sms().bits(0b111) });
+                        }
+                    }
+
+                    tim.psc.write(|w| unsafe { w.bits(u32::from(config.prescaler - 1)) });
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+
+                    /* Load the prescaler right away, only counter overflows flag an update */
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    /* The flags are cleared by writing 0, writing 1 has no effect */
+                    tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0001) });
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    PulseCounter {
+                        tim,
+                        input,
+                        overflows: 0,
+                    }
+                }
+
+                overflow_counter!();
+
+                /// Returns the number of counted edges, divided by the prescaler
+                pub fn count(&mut self) -> u64 {
+                    self.extended_count()
+                }
+
+                /// Sets the count to 0
+                pub fn reset(&mut self) {
+                    self.tim.cnt.write(|w| unsafe { w.bits(0) });
+                    self.tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0001) });
+                    self.overflows = 0;
+                }
+
+                /// Enables the overflow interrupt, whose handler has to call `on_overflow`
+                pub fn listen(&mut self) {
+                    self.tim.dier.modify(|_, w| w.uie().set_bit());
+                }
+
+                /// Disables the overflow interrupt
+                pub fn unlisten(&mut self) {
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                }
+
+                /// Stops counting and releases the TIM peripheral and input
+                pub fn release(self) -> ($TIM, INPUT) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.input)
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+counter! {
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr),
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr),
+}
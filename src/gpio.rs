@@ -20,8 +20,10 @@ pub struct AF5;
 pub struct AF6;
 pub struct AF7;
 
-pub struct Alternate<MODE> {
+/// Alternate function mode (type state), with a push pull or open drain output
+pub struct Alternate<MODE, OTYPE = PushPull> {
     _mode: PhantomData<MODE>,
+    _otype: PhantomData<OTYPE>,
 }
 
 /// Input mode (type state)
@@ -52,6 +54,31 @@ pub struct PushPull;
 use hal::digital::{InputPin, OutputPin, StatefulOutputPin};
 use stm32;
 
+/* Public in a private module, so that it can bound the public impls of the drivers but users can't
+ * name it and mess with the pins of a peripheral */
+mod sealed {
+    /// Software control over a pin in alternate function mode
+    ///
+    /// This is used by peripheral drivers which temporarily need to drive their pins by hand, e.g.
+    /// to recover a stuck bus.
+    pub trait GpioOverride {
+        /// Disconnects the pin from the peripheral and configures it as an open drain output,
+        /// returns whether the output was open drain before
+        fn set_open_drain_output(&mut self) -> bool;
+
+        /// Hands the pin back to the peripheral, restoring the output type `open_drain`
+        fn set_alternate(&mut self, open_drain: bool);
+
+        /// Drives the output low or releases it
+        fn set_output_high(&mut self, high: bool);
+
+        /// Returns whether the pin reads high
+        fn is_input_high(&self) -> bool;
+    }
+}
+
+pub(crate) use self::sealed::GpioOverride;
+
 /// Fully erased pin
 // We can just pretend it's gpioa. It's modified using the bits and it can only be constructed out of already existing pins
 pub struct Pin<MODE> {
@@ -118,7 +145,7 @@ macro_rules! gpio {
 
             use stm32::RCC;
             use super::{
-                Alternate, Floating, GpioExt, Input, OpenDrain, Output,
+                Alternate, Floating, GpioExt, GpioOverride, Input, OpenDrain, Output,
                 PullDown, PullUp, PushPull, AF0, AF1, AF2, AF3, AF4, AF5, AF6, AF7, Pin
             };
 
@@ -458,7 +485,7 @@ macro_rules! gpio {
                     }
                 }
 
-                impl<MODE> $PXi<Alternate<MODE>> {
+                impl<MODE, OTYPE> $PXi<Alternate<MODE, OTYPE>> {
                     /// Enables / disables the internal pull up
                     pub fn internal_pull_up(&mut self, on: bool) {
                         let offset = 2 * $i;
@@ -472,14 +499,53 @@ macro_rules! gpio {
 
                 impl<MODE> $PXi<Alternate<MODE>> {
                     /// Turns pin alternate configuration pin into open drain
-                    pub fn set_open_drain(self) -> Self {
+                    pub fn set_open_drain(self) -> $PXi<Alternate<MODE, OpenDrain>> {
                         let offset = $i;
                         unsafe {
                             &(*$GPIOX::ptr()).otyper.modify(|r, w| {
                                 w.bits(r.bits() | (1 << offset))
                             })};
 
-                        self
+                        $PXi { _mode: PhantomData }
+                    }
+                }
+
+                impl<MODE, OTYPE> GpioOverride for $PXi<Alternate<MODE, OTYPE>> {
+                    fn set_open_drain_output(&mut self) -> bool {
+                        let offset = 2 * $i;
+                        unsafe {
+                            let open_drain = (*$GPIOX::ptr()).otyper.read().bits() & (0b1 << $i) != 0;
+                            &(*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits(r.bits() | (0b1 << $i))
+                            });
+                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | (0b01 << offset))
+                            });
+                            open_drain
+                        }
+                    }
+
+                    fn set_alternate(&mut self, open_drain: bool) {
+                        let offset = 2 * $i;
+                        unsafe {
+                            &(*$GPIOX::ptr()).otyper.modify(|r, w| {
+                                w.bits((r.bits() & !(0b1 << $i)) | ((open_drain as u32) << $i))
+                            });
+                            &(*$GPIOX::ptr()).moder.modify(|r, w| {
+                                w.bits((r.bits() & !(0b11 << offset)) | (0b10 << offset))
+                            });
+                        }
+                    }
+
+                    fn set_output_high(&mut self, high: bool) {
+                        let bit = if high { $i } else { $i + 16 };
+                        // NOTE(unsafe) atomic write to a stateless register
+                        unsafe { (*$GPIOX::ptr()).bsrr.write(|w| w.bits(1 << bit)) }
+                    }
+
+                    fn is_input_high(&self) -> bool {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { (*$GPIOX::ptr()).idr.read().bits() & (1 << $i) != 0 }
                     }
                 }
 
@@ -600,7 +666,7 @@ gpio!(GPIOA, gpioa, iopaen, PA, [
 ]);
 
 // TSSOP20
-#[cfg(any(feature = "stm32f030x4"))]
+#[cfg(any(feature = "stm32f030f4"))]
 gpio!(GPIOB, gpiob, iopben, PB, [
     PB1: (pb1, 1, Input<Floating>),
 ]);
@@ -1,20 +1,161 @@
-use stm32::{I2C1, I2C2, RCC};
+use stm32::{i2c1, I2C1, I2C2, RCC, SYSCFG};
 
-use hal::blocking::i2c::{Write, WriteRead};
+use hal::blocking::i2c::{
+    Operation, Read, SevenBitAddress, Transactional, Write, WriteIter, WriteIterRead, WriteRead,
+};
 
 use core::cmp;
+use cortex_m::asm;
 use gpio::*;
-use time::{KiloHertz, U32Ext};
+use rcc::Clocks;
+use time::{Hertz, KiloHertz, MicroSeconds};
 
 /// I2C abstraction
 pub struct I2c<I2C, PINS> {
     i2c: I2C,
     pins: PINS,
+    timeout: u32,
+    clocks: Clocks,
 }
 
-pub trait Pins<I2c> {}
+/// Iterator over the 7 bit addresses answering on an I2C bus, see `I2c::scan`
+pub struct Scan<'a, I2C: 'a, PINS: 'a> {
+    i2c: &'a mut I2c<I2C, PINS>,
+    next: u8,
+}
+
+/// SMBus host on top of I2C1
+///
+/// All commands are sent with packet error checking (PEC). A slave holding the clock low for
+/// more than 25 ms is detected and reported as `Error::Timeout`.
+pub struct Smbus<PINS> {
+    i2c: I2c<I2C1, PINS>,
+}
+
+/// I2C slave abstraction
+pub struct I2cSlave<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+    timeout: u32,
+    clocks: Clocks,
+}
 
-// TODO Add all possible pin bindings
+/// Bus events seen by an I2C slave
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlaveEvent {
+    /// The master wants to write to `address`, receive the data with `read`
+    WriteRequested { address: u8 },
+    /// The master wants to read from `address`, send the data with `write`
+    ReadRequested { address: u8 },
+    /// The master ended the transfer with a STOP condition
+    Stop,
+}
+
+/// I2C slave interrupt events
+pub enum Event {
+    /// One of the own addresses was matched
+    AddressMatch,
+    /// A STOP condition was detected
+    Stop,
+    /// The master didn't acknowledge a byte
+    Nack,
+    /// New data has been received
+    Rxne,
+    /// New data can be sent
+    Txis,
+    /// A bus error, arbitration loss or overrun occurred
+    Error,
+}
+
+/// 10 bit slave address, see `I2c::write_10bit` and friends
+///
+/// Unlike embedded-hal's `TenBitAddress`, which is a plain `u16`, this can only hold a value that
+/// fits in 10 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TenBitAddress(u16);
+
+impl TenBitAddress {
+    /// Returns the address, or `None` if it doesn't fit in 10 bits
+    pub fn new(address: u16) -> Option<Self> {
+        if address <= 0x3FF {
+            Some(TenBitAddress(address))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value of the address
+    pub fn get(self) -> u16 {
+        self.0
+    }
+}
+
+/// Slave address of a transfer, either a `SevenBitAddress` or a `TenBitAddress`
+trait Address: Copy {
+    /// Returns the value of the SADD field
+    fn sadd(self) -> u16;
+
+    /// Returns whether the address is sent in 10 bit mode
+    fn is_ten_bit(self) -> bool;
+}
+
+impl Address for SevenBitAddress {
+    fn sadd(self) -> u16 {
+        u16::from(self) << 1
+    }
+
+    fn is_ten_bit(self) -> bool {
+        false
+    }
+}
+
+impl Address for TenBitAddress {
+    fn sadd(self) -> u16 {
+        self.0
+    }
+
+    fn is_ten_bit(self) -> bool {
+        true
+    }
+}
+
+/// SCL pin of an I2C peripheral, the pin has to be in open drain alternate function mode
+pub trait SclPin<I2C> {
+    // Bit of the pin's own Fm+ high current drive in SYSCFG_CFGR1, if it has one
+    #[doc(hidden)]
+    const FMP: u32 = 0;
+}
+
+/// SDA pin of an I2C peripheral, the pin has to be in open drain alternate function mode
+pub trait SdaPin<I2C> {
+    // Bit of the pin's own Fm+ high current drive in SYSCFG_CFGR1, if it has one
+    #[doc(hidden)]
+    const FMP: u32 = 0;
+}
+
+pub trait Pins<I2C> {
+    #[doc(hidden)]
+    const FMP: u32;
+}
+
+impl<I2C, SCL, SDA> Pins<I2C> for (SCL, SDA)
+where
+    SCL: SclPin<I2C>,
+    SDA: SdaPin<I2C>,
+{
+    const FMP: u32 = SCL::FMP | SDA::FMP;
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl SclPin<I2C1> for gpioa::PA9<Alternate<AF4, OpenDrain>> {}
 #[cfg(any(
     feature = "stm32f030f4",
     feature = "stm32f030k6",
@@ -24,31 +165,462 @@ pub trait Pins<I2c> {}
     feature = "stm32f030r8",
     feature = "stm32f030rc"
 ))]
-impl Pins<I2C1> for (gpioa::PA9<Alternate<AF4>>, gpioa::PA10<Alternate<AF4>>) {}
+impl SdaPin<I2C1> for gpioa::PA10<Alternate<AF4, OpenDrain>> {}
+
+#[cfg(any(
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl SclPin<I2C1> for gpiob::PB6<Alternate<AF1, OpenDrain>> {
+    // I2C_PB6_FMP
+    const FMP: u32 = 1 << 16;
+}
+#[cfg(any(
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl SdaPin<I2C1> for gpiob::PB7<Alternate<AF1, OpenDrain>> {
+    // I2C_PB7_FMP
+    const FMP: u32 = 1 << 17;
+}
+
 #[cfg(any(
+    feature = "stm32f030c6",
     feature = "stm32f030c8",
     feature = "stm32f030cc",
     feature = "stm32f030r8",
     feature = "stm32f030rc"
 ))]
-impl Pins<I2C2> for (gpiob::PB10<Alternate<AF1>>, gpiob::PB11<Alternate<AF1>>) {}
+impl SclPin<I2C1> for gpiob::PB8<Alternate<AF1, OpenDrain>> {
+    // I2C_PB8_FMP
+    const FMP: u32 = 1 << 18;
+}
+#[cfg(any(
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl SdaPin<I2C1> for gpiob::PB9<Alternate<AF1, OpenDrain>> {
+    // I2C_PB9_FMP
+    const FMP: u32 = 1 << 19;
+}
+
+// STM32F030x6 only has I2C1 on these pins
+#[cfg(feature = "stm32f030c6")]
+impl SclPin<I2C1> for gpiob::PB10<Alternate<AF1, OpenDrain>> {}
+#[cfg(feature = "stm32f030c6")]
+impl SdaPin<I2C1> for gpiob::PB11<Alternate<AF1, OpenDrain>> {}
+
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+impl SclPin<I2C1> for gpiof::PF1<Alternate<AF1, OpenDrain>> {}
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+impl SdaPin<I2C1> for gpiof::PF0<Alternate<AF1, OpenDrain>> {}
 
-#[derive(Debug)]
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl SclPin<I2C2> for gpiob::PB10<Alternate<AF1, OpenDrain>> {}
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl SdaPin<I2C2> for gpiob::PB11<Alternate<AF1, OpenDrain>> {}
+
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+impl SclPin<I2C2> for gpioa::PA11<Alternate<AF5, OpenDrain>> {}
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+impl SdaPin<I2C2> for gpioa::PA12<Alternate<AF5, OpenDrain>> {}
+
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+impl SclPin<I2C2> for gpiob::PB13<Alternate<AF5, OpenDrain>> {}
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+impl SdaPin<I2C2> for gpiob::PB14<Alternate<AF5, OpenDrain>> {}
+
+// Maximum number of bytes transferred without reloading NBYTES
+const MAX_NBYTES: usize = 255;
+
+// Default time in microseconds without progress before a transfer is aborted
+const DEFAULT_TIMEOUT: u32 = 100_000;
+
+/// Deadline of a busy wait, checked in steps of a microsecond
+struct Deadline {
+    remaining: u32,
+    cycles: u32,
+}
+
+impl Deadline {
+    fn new(us: u32, clocks: &Clocks) -> Self {
+        Deadline {
+            remaining: us,
+            cycles: cmp::max(clocks.sysclk().0 / 1_000_000, 1),
+        }
+    }
+
+    /// Returns whether the deadline passed, otherwise waits for the next step
+    fn expired(&mut self) -> bool {
+        if self.remaining == 0 {
+            true
+        } else {
+            self.remaining -= 1;
+            asm::delay(self.cycles);
+            false
+        }
+    }
+}
+
+/// I2C error
+#[derive(Debug, PartialEq, Eq)]
 pub enum Error {
-    OVERRUN,
-    NACK,
+    /// Overrun or underrun occurred
+    Overrun,
+    /// The slave didn't acknowledge its address or a data byte
+    Nack,
+    /// A misplaced START or STOP condition was detected
+    Bus,
+    /// Arbitration was lost to another master
+    Arbitration,
+    /// The bus didn't make progress within the configured timeout
+    Timeout,
+    /// The received packet error checking byte didn't match
+    Pec,
+}
+
+impl Error {
+    #[deprecated(note = "renamed to `Error::Overrun`")]
+    pub const OVERRUN: Error = Error::Overrun;
+    #[deprecated(note = "renamed to `Error::Nack`")]
+    pub const NACK: Error = Error::Nack;
+}
+
+/// I2C bus configuration
+#[derive(Clone, Copy)]
+pub struct Config {
+    speed: KiloHertz,
+    analog_filter: bool,
+    digital_filter: u8,
+    fast_mode_plus: bool,
+}
+
+impl Config {
+    /// Creates a configuration for a bus `speed` of up to 1 MHz
+    ///
+    /// The analog filter is enabled and the digital filter is disabled, the Fm+ drive is enabled
+    /// for speeds above 400 kHz.
+    pub fn new<F>(speed: F) -> Self
+    where
+        F: Into<KiloHertz>,
+    {
+        let speed = speed.into();
+        Config {
+            speed,
+            analog_filter: true,
+            digital_filter: 0,
+            fast_mode_plus: speed.0 > 400,
+        }
+    }
+
+    /// Enables / disables the analog filter suppressing spikes of 50 ns to 260 ns
+    pub fn analog_filter(mut self, on: bool) -> Self {
+        self.analog_filter = on;
+        self
+    }
+
+    /// Suppresses spikes of up to `cycles` I2C clock cycles (0 to 15), 0 disables the filter
+    pub fn digital_filter(mut self, cycles: u8) -> Self {
+        assert!(cycles <= 15);
+        self.digital_filter = cycles;
+        self
+    }
+
+    /// Enables / disables the Fm+ high current drive of all pins connected to the peripheral
+    pub fn fast_mode_plus(mut self, on: bool) -> Self {
+        self.fast_mode_plus = on;
+        self
+    }
+}
+
+impl From<KiloHertz> for Config {
+    fn from(speed: KiloHertz) -> Self {
+        Config::new(speed)
+    }
+}
+
+/// Values of the I2C timing register for a given I2C clock and bus speed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub presc: u8,
+    pub scldel: u8,
+    pub sdadel: u8,
+    pub sclh: u8,
+    pub scll: u8,
+}
+
+// Minimum SCL low and high periods, data setup time, maximum data hold time and maximum rise and
+// fall times of the I2C specification in ns
+struct ModeTimings {
+    low: u32,
+    high: u32,
+    setup: u32,
+    hold: u32,
+    rise: u32,
+    fall: u32,
+}
+
+const STANDARD_MODE: ModeTimings = ModeTimings {
+    low: 4_700,
+    high: 4_000,
+    setup: 250,
+    hold: 3_450,
+    rise: 1_000,
+    fall: 300,
+};
+
+const FAST_MODE: ModeTimings = ModeTimings {
+    low: 1_300,
+    high: 600,
+    setup: 100,
+    hold: 900,
+    rise: 300,
+    fall: 300,
+};
+
+const FAST_MODE_PLUS: ModeTimings = ModeTimings {
+    low: 500,
+    high: 260,
+    setup: 50,
+    hold: 450,
+    rise: 120,
+    fall: 120,
+};
+
+// Minimum and maximum spike width suppressed by the analog filter in ns
+const ANALOG_FILTER_MIN: u32 = 50;
+const ANALOG_FILTER_MAX: u32 = 260;
+
+impl Timing {
+    /// Calculates the timing for the bus speed and filters of `config` from the I2C clock
+    /// `i2cclk`
+    ///
+    /// The setup and hold times follow the constraints of the reference manual for the
+    /// standard, fast or fast-mode plus mode matching the speed. Returns `None` if the speed
+    /// can't be reached with this I2C clock.
+    pub fn new(i2cclk: Hertz, config: &Config) -> Option<Timing> {
+        let freq = u64::from(i2cclk.0);
+        let speed: Hertz = config.speed.into();
+
+        let mode = if speed.0 == 0 {
+            return None;
+        } else if speed.0 <= 100_000 {
+            STANDARD_MODE
+        } else if speed.0 <= 400_000 {
+            FAST_MODE
+        } else if speed.0 <= 1_000_000 {
+            FAST_MODE_PLUS
+        } else {
+            return None;
+        };
+
+        // Number of I2C clock cycles in `ns`, rounded down or up
+        let cycles = |ns: u32| (u64::from(ns) * freq / 1_000_000_000) as i64;
+        let cycles_ceil = |ns: u32| (u64::from(ns) * freq).div_ceil(1_000_000_000) as i64;
+
+        // Delays of the analog filter, the digital filter delays by its number of cycles
+        let (af_min, af_max) = if config.analog_filter {
+            (ANALOG_FILTER_MIN, ANALOG_FILTER_MAX)
+        } else {
+            (0, 0)
+        };
+        let dnf = i64::from(config.digital_filter);
+
+        // The I2C clock period has to be short enough to detect the SCL low and high periods
+        if (4 + dnf as u64) * 1_000_000_000 >= u64::from(mode.low - af_min) * freq
+            || 1_000_000_000 >= u64::from(mode.high) * freq
+        {
+            return None;
+        }
+
+        // The SCL low and high periods are extended by the synchronisation to SCL, which takes
+        // the filter delays and at least two I2C clock cycles. Rise and fall times are ignored,
+        // a slow bus just ends up slower than requested.
+        let sync = cycles(af_min) + dnf + 2;
+        let period = i64::from(i2cclk.0 / speed.0);
+
+        for presc in 0..16 {
+            let scale = presc + 1;
+            let div_ceil = |value: i64| if value <= 0 { 0 } else { (value + scale - 1) / scale };
+
+            // tSCLDEL >= tr + tSU;DAT
+            let scldel = div_ceil(cycles_ceil(mode.rise + mode.setup)) - 1;
+
+            // tSDADEL >= tf - tAF(min) - tDNF - 3 * tI2CCLK
+            // tSDADEL <= tHD;DAT(max) - tr - tAF(max) - tDNF - 4 * tI2CCLK
+            // The hold time of the faster modes can't always be met with the filters enabled, in
+            // that case only the minimum data delay is kept
+            let sdadel_min = cmp::max(cycles_ceil(mode.fall - af_min) - dnf - 3, 0);
+            let sdadel_max = cycles(mode.hold - mode.rise - af_max) - dnf - 4;
+            let sdadel = div_ceil(sdadel_min);
+
+            if scldel > 15
+                || sdadel > 15
+                || (sdadel_min <= sdadel_max && sdadel * scale > sdadel_max)
+            {
+                continue;
+            }
+
+            let low = cmp::max(div_ceil(cycles_ceil(mode.low) - sync), 1);
+            let high = cmp::max(div_ceil(cycles_ceil(mode.high) - sync), 1);
+            let total = (period - 2 * sync) / scale;
+
+            if total < low + high {
+                return None;
+            }
+
+            // Spread the remaining time over the low and high periods
+            let low = cmp::min(low + (total - low - high) * low / (low + high), 256);
+            let high = total - low;
+
+            if low < 1 || high < 1 || low > 256 || high > 256 {
+                continue;
+            }
+
+            return Some(Timing {
+                presc: presc as u8,
+                scldel: scldel as u8,
+                sdadel: sdadel as u8,
+                sclh: (high - 1) as u8,
+                scll: (low - 1) as u8,
+            });
+        }
+
+        None
+    }
+}
+
+fn is_read(operation: &Operation) -> bool {
+    match *operation {
+        Operation::Read(_) => true,
+        Operation::Write(_) => false,
+    }
+}
+
+fn operation_len(operation: &Operation) -> usize {
+    match *operation {
+        Operation::Read(ref buffer) => buffer.len(),
+        Operation::Write(bytes) => bytes.len(),
+    }
+}
+
+/// Returns the error flagged in the interrupt and status register, NACKs aren't included
+fn bus_error(isr: &i2c1::isr::R) -> Option<Error> {
+    if isr.berr().bit_is_set() {
+        Some(Error::Bus)
+    } else if isr.arlo().bit_is_set() {
+        Some(Error::Arbitration)
+    } else if isr.ovr().bit_is_set() {
+        Some(Error::Overrun)
+    } else if isr.timeout().bit_is_set() {
+        Some(Error::Timeout)
+    } else if isr.pecerr().bit_is_set() {
+        Some(Error::Pec)
+    } else {
+        None
+    }
+}
+
+/// Releases the bus lines and resets the state machine while keeping the configuration
+fn software_reset(i2c: &i2c1::RegisterBlock) {
+    /* PE has to stay low for at least 3 APB cycles */
+    i2c.cr1.modify(|_, w| w.pe().clear_bit());
+    for _ in 0..3 {
+        i2c.cr1.read();
+    }
+    i2c.cr1.modify(|_, w| w.pe().set_bit());
+}
+
+/// Clears all error and transfer flags and flushes the transmit data register
+fn clear_flags(i2c: &i2c1::RegisterBlock) {
+    i2c.icr.write(|w| {
+        w.berrcf()
+         .set_bit()
+         .arlocf()
+         .set_bit()
+         .ovrcf()
+         .set_bit()
+         .timoutcf()
+         .set_bit()
+         .peccf()
+         .set_bit()
+         .nackcf()
+         .set_bit()
+         .stopcf()
+         .set_bit()
+    });
+    i2c.isr.write(|w| w.txe().set_bit());
 }
 
 macro_rules! i2c {
-    ($($I2C:ident: ($i2c:ident, $i2cXen:ident, $i2cXrst:ident, $apbenr:ident, $apbrstr:ident ),)+) => {
+    ($($I2C:ident: ($i2c:ident, $i2cXen:ident, $i2cXrst:ident, $apbenr:ident, $apbrstr:ident, $i2cclk:ident, $fmp:ident),)+) => {
         $(
             impl<PINS> I2c<$I2C, PINS> {
-                pub fn $i2c(i2c: $I2C, pins: PINS, speed: KiloHertz) -> Self
+                /// Configures the I2C peripheral as a master, `config` can be a bus speed or
+                /// a `Config`
+                pub fn $i2c<C>(i2c: $I2C, pins: PINS, config: C, clocks: Clocks) -> Self
+                where
+                    PINS: Pins<$I2C>,
+                    C: Into<Config>,
+                {
+                    Self::reset(&i2c, &config.into(), clocks);
+
+                    /* Enable the I2C processing */
+                    i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    I2c {
+                        i2c,
+                        pins,
+                        timeout: DEFAULT_TIMEOUT,
+                        clocks,
+                    }
+                }
+
+                fn reset(i2c: &$I2C, config: &Config, clocks: Clocks)
                 where
                     PINS: Pins<$I2C>,
                 {
+                    // Calculate settings for I2C speed modes
+                    let timing = Timing::new(clocks.$i2cclk(), config)
+                        .expect("I2C speed not reachable with the I2C clock");
+
                     // NOTE(unsafe) This executes only during initialisation
                     let rcc = unsafe { &(*RCC::ptr()) };
+                    let syscfg = unsafe { &(*SYSCFG::ptr()) };
+
+                    /* Set the high current drive of the pins for fast-mode plus, PB6 to PB9 need
+                     * their own bits on top of the peripheral's */
+                    rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+                    syscfg.cfgr1.modify(|r, w| unsafe {
+                        w.bits(if config.fast_mode_plus {
+                            r.bits() | PINS::FMP
+                        } else {
+                            r.bits() & !PINS::FMP
+                        })
+                        .$fmp()
+                        .bit(config.fast_mode_plus)
+                    });
 
                     /* Enable clock for I2C */
                     rcc.$apbenr.modify(|_, w| w.$i2cXen().set_bit());
@@ -60,93 +632,149 @@ macro_rules! i2c {
                     /* Make sure the I2C unit is disabled so we can configure it */
                     i2c.cr1.modify(|_, w| w.pe().clear_bit());
 
-                    // Calculate settings for I2C speed modes
-                    let presc;
-                    let scldel;
-                    let sdadel;
-                    let sclh;
-                    let scll;
-
-                    // We're using HSI here which runs at a fixed 8MHz
-                    const FREQ: u32 = 8_000_000;
-
-                    // Normal I2C speeds use a different scaling than fast mode below
-                    if speed <= 100_u32.khz() {
-                        presc = 1;
-                        scll = cmp::max((((FREQ >> presc) >> 1) / speed.0) - 1, 255) as u8;
-                        sclh = scll - 4;
-                        sdadel = 2;
-                        scldel = 4;
-                    } else {
-                        presc = 0;
-                        scll = cmp::max((((FREQ >> presc) >> 1) / speed.0) - 1, 255) as u8;
-                        sclh = scll - 6;
-                        sdadel = 1;
-                        scldel = 3;
-                    }
+                    /* Configure the noise filters */
+                    i2c.cr1.modify(|_, w| {
+                        w.anfoff()
+                         .bit(!config.analog_filter)
+                         .dnf()
+                         .bits(config.digital_filter)
+                    });
 
-                    /* Enable I2C signal generator, and configure I2C for 400KHz full speed */
+                    /* Enable I2C signal generator, and configure I2C for the requested speed */
                     i2c.timingr.write(|w| {
                         w.presc()
-                         .bits(presc)
+                         .bits(timing.presc)
                          .scldel()
-                         .bits(scldel)
+                         .bits(timing.scldel)
                          .sdadel()
-                         .bits(sdadel)
+                         .bits(timing.sdadel)
                          .sclh()
-                         .bits(sclh)
+                         .bits(timing.sclh)
                          .scll()
-                         .bits(scll)
+                         .bits(timing.scll)
                     });
-
-                    /* Enable the I2C processing */
-                    i2c.cr1.modify(|_, w| w.pe().set_bit());
-
-                    I2c { i2c, pins }
                 }
 
                 pub fn release(self) -> ($I2C, PINS) {
                     (self.i2c, self.pins)
                 }
 
+                /// Sets the time without progress after which a stuck transfer is aborted with
+                /// `Error::Timeout`
+                pub fn set_timeout<T: Into<MicroSeconds>>(&mut self, timeout: T) {
+                    self.timeout = timeout.into().0;
+                }
+
+                /// Sends only the address to `addr` and returns whether the slave acknowledged it
+                pub fn probe(&mut self, addr: SevenBitAddress) -> Result<bool, Error> {
+                    self.set_nbytes(0);
+                    self.start(addr, false, true);
+
+                    match self.wait_stop() {
+                        Ok(()) => Ok(true),
+                        Err(Error::Nack) => Ok(false),
+                        Err(error) => Err(error),
+                    }
+                }
+
+                /// Returns an iterator over all non-reserved 7 bit addresses acknowledging a probe
+                pub fn scan<'a>(&'a mut self) -> Scan<'a, $I2C, PINS> {
+                    Scan {
+                        i2c: self,
+                        next: 0x08,
+                    }
+                }
+
+                /// Waits until `flag` is set, aborting the transfer on any error condition
+                fn wait<F>(&self, flag: F) -> Result<(), Error>
+                where
+                    F: Fn(&i2c1::isr::R) -> bool,
+                {
+                    let mut deadline = Deadline::new(self.timeout, &self.clocks);
+                    loop {
+                        let isr = self.i2c.isr.read();
+
+                        let error = if let Some(error) = bus_error(&isr) {
+                            error
+                        } else if isr.nackf().bit_is_set() {
+                            Error::Nack
+                        } else if flag(&isr) {
+                            return Ok(());
+                        } else if deadline.expired() {
+                            Error::Timeout
+                        } else {
+                            continue;
+                        };
+
+                        self.abort(&error);
+                        return Err(error);
+                    }
+                }
+
+                /// Puts the peripheral back into an idle state after an error
+                fn abort(&self, error: &Error) {
+                    match *error {
+                        Error::Nack => {
+                            /* A NACK makes the master send a STOP condition by itself */
+                            let mut deadline = Deadline::new(self.timeout, &self.clocks);
+                            while self.i2c.isr.read().stopf().bit_is_clear() && !deadline.expired() {}
+                        }
+                        _ => software_reset(&self.i2c),
+                    }
+
+                    clear_flags(&self.i2c);
+                }
+
+                /// Waits for the STOP condition that ends a transfer
+                fn wait_stop(&self) -> Result<(), Error> {
+                    self.wait(|isr| isr.stopf().bit_is_set())?;
+                    self.i2c.icr.write(|w| w.stopcf().set_bit());
+                    Ok(())
+                }
+
                 fn send_byte(&self, byte: u8) -> Result<(), Error> {
                     /* Wait until we're ready for sending */
-                    while self.i2c.isr.read().txis().bit_is_clear() {}
+                    self.wait(|isr| isr.txis().bit_is_set())?;
 
                     /* Push out a byte of data */
                     self.i2c.txdr.write(|w| unsafe { w.bits(u32::from(byte)) });
 
-                    /* If we received a NACK, then this is an error */
-                    if self.i2c.isr.read().nackf().bit_is_set() {
-                        self.i2c
-                            .icr
-                            .write(|w| w.stopcf().set_bit().nackcf().set_bit());
-                        return Err(Error::NACK);
-                    }
-
                     Ok(())
                 }
 
                 fn recv_byte(&self) -> Result<u8, Error> {
-                    while self.i2c.isr.read().rxne().bit_is_clear() {}
+                    self.wait(|isr| isr.rxne().bit_is_set())?;
                     let value = self.i2c.rxdr.read().bits() as u8;
                     Ok(value)
                 }
-            }
 
-            impl<PINS> WriteRead for I2c<$I2C, PINS> {
-                type Error = Error;
+                /// Loads NBYTES for the next chunk of a transfer with `remaining` bytes left,
+                /// the RELOAD mode is used for transfers longer than 255 bytes
+                fn set_nbytes(&self, remaining: usize) {
+                    self.i2c.cr2.modify(|_, w| {
+                        w.nbytes()
+                         .bits(cmp::min(remaining, MAX_NBYTES) as u8)
+                         .reload()
+                         .bit(remaining > MAX_NBYTES)
+                    });
+                }
+
+                /// Starts a transfer to `addr`, a START while the previous transfer is still
+                /// pending results in a repeated START
+                fn start<A: Address>(&self, addr: A, read: bool, autoend: bool) {
+                    /* A restarted read of a 10 bit address only needs the header, the full address
+                     * was already sent with the preceding write */
+                    let restart = self.i2c.isr.read().tc().bit_is_set();
 
-                fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
-                    /* Set up current address, we're trying a "read" command and not going to set anything
-                     * and make sure we end a non-NACKed read (i.e. if we found a device) properly */
                     self.i2c.cr2.modify(|_, w| {
                         w.sadd()
-                         .bits(u16::from(addr) << 1)
-                         .nbytes()
-                         .bits(bytes.len() as u8)
+                         .bits(addr.sadd())
+                         .add10()
+                         .bit(addr.is_ten_bit())
+                         .head10r()
+                         .bit(read && restart)
                          .rd_wrn()
-                         .clear_bit()
+                         .bit(read)
                          .autoend()
                          .clear_bit()
                     });
@@ -154,95 +782,508 @@ macro_rules! i2c {
                     /* Send a START condition */
                     self.i2c.cr2.modify(|_, w| w.start().set_bit());
 
-                    /* Wait until the transmit buffer is empty and there hasn't been either a NACK or STOP
-                     * being received */
-                    let mut isr;
-                    while {
-                        isr = self.i2c.isr.read();
-                        isr.txis().bit_is_clear()
-                            && isr.nackf().bit_is_clear()
-                            && isr.stopf().bit_is_clear()
-                            && isr.tc().bit_is_clear()
-                    } {}
-
-                    /* If we received a NACK, then this is an error */
-                    if isr.nackf().bit_is_set() {
-                        self.i2c
-                            .icr
-                            .write(|w| w.stopcf().set_bit().nackcf().set_bit());
-                        return Err(Error::NACK);
+                    /* Send the autoend after setting the start to get a restart */
+                    if autoend {
+                        self.i2c.cr2.modify(|_, w| w.autoend().set_bit());
+                    }
+                }
+
+                /// Loads the next chunk once `done` bytes of a `len` bytes long transfer are
+                /// handled and the current chunk is exhausted
+                fn reload(&self, done: usize, len: usize) -> Result<(), Error> {
+                    if done > 0 && done % MAX_NBYTES == 0 {
+                        /* Wait until the previous chunk was handled before reloading */
+                        self.wait(|isr| isr.tcr().bit_is_set())?;
+                        self.set_nbytes(len - done);
                     }
+                    Ok(())
+                }
 
+                /// Sends `bytes` as part of a `len` bytes long transfer of which `done` bytes
+                /// are already sent, returns the number of bytes sent afterwards
+                fn write_bytes(&self, bytes: &[u8], mut done: usize, len: usize) -> Result<usize, Error> {
                     for c in bytes {
+                        self.reload(done, len)?;
                         self.send_byte(*c)?;
+                        done += 1;
                     }
 
-                    /* Wait until data was sent */
-                    while self.i2c.isr.read().tc().bit_is_clear() {}
+                    Ok(done)
+                }
 
-                    /* Set up current address, we're trying a "read" command and not going to set anything
-                     * and make sure we end a non-NACKed read (i.e. if we found a device) properly */
-                    self.i2c.cr2.modify(|_, w| {
-                        w.sadd()
-                         .bits(u16::from(addr) << 1)
-                         .nbytes()
-                         .bits(buffer.len() as u8)
-                         .rd_wrn()
-                         .set_bit()
-                    });
+                /// Fills `buffer` as part of a `len` bytes long transfer of which `done` bytes
+                /// are already received, returns the number of bytes received afterwards
+                fn read_bytes(&self, buffer: &mut [u8], mut done: usize, len: usize) -> Result<usize, Error> {
+                    for c in buffer {
+                        self.reload(done, len)?;
+                        *c = self.recv_byte()?;
+                        done += 1;
+                    }
 
-                    /* Send a START condition */
-                    self.i2c.cr2.modify(|_, w| w.start().set_bit());
+                    Ok(done)
+                }
 
-                    /* Send the autoend after setting the start to get a restart */
-                    self.i2c.cr2.modify(|_, w| w.autoend().set_bit());
+                /// Starts a write of an unknown number of bytes, every byte is loaded as a chunk
+                /// of its own while more bytes follow
+                fn write_iter<A, B>(&self, addr: A, bytes: B, autoend: bool) -> Result<(), Error>
+                where
+                    A: Address,
+                    B: IntoIterator<Item = u8>,
+                {
+                    let mut bytes = bytes.into_iter().peekable();
+                    let mut byte = bytes.next();
 
-                    /* Read in all bytes */
-                    for c in buffer.iter_mut() {
-                        *c = self.recv_byte()?;
-                    }
+                    let more = bytes.peek().is_some();
+                    self.i2c.cr2.modify(|_, w| w.nbytes().bits(byte.is_some() as u8).reload().bit(more));
+                    self.start(addr, false, autoend);
 
-                    /* Clear flags if they somehow ended up set */
-                    self.i2c
-                        .icr
-                        .write(|w| w.stopcf().set_bit().nackcf().set_bit());
+                    while let Some(c) = byte {
+                        self.send_byte(c)?;
+
+                        byte = bytes.next();
+                        if byte.is_some() {
+                            self.wait(|isr| isr.tcr().bit_is_set())?;
+                            let more = bytes.peek().is_some();
+                            self.i2c.cr2.modify(|_, w| w.nbytes().bits(1).reload().bit(more));
+                        }
+                    }
 
                     Ok(())
                 }
             }
 
+            impl<SCL, SDA> I2c<$I2C, (SCL, SDA)>
+            where
+                SCL: GpioOverride,
+                SDA: GpioOverride,
+            {
+                /// Frees the bus from a slave holding SDA low, e.g. after a reset in the middle of
+                /// a transfer
+                ///
+                /// The pins are driven as open drain GPIOs to clock out up to nine SCL pulses
+                /// followed by a STOP condition, afterwards they are handed back to the I2C
+                /// peripheral. Returns `Error::Bus` if SDA is still held low.
+                pub fn recover_bus(&mut self) -> Result<(), Error> {
+                    /* Bit-bang at 100kHz at most */
+                    let half_period = self.clocks.sysclk().0 / 200_000;
+                    let (timeout, clocks) = (self.timeout, self.clocks);
+                    let (ref mut scl, ref mut sda) = self.pins;
+
+                    /* Disconnect the peripheral from the bus, starting with both lines released */
+                    self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+                    scl.set_output_high(true);
+                    sda.set_output_high(true);
+                    let scl_open_drain = scl.set_open_drain_output();
+                    let sda_open_drain = sda.set_open_drain_output();
+
+                    for _ in 0..9 {
+                        if sda.is_input_high() {
+                            break;
+                        }
+
+                        scl.set_output_high(false);
+                        asm::delay(half_period);
+                        scl.set_output_high(true);
+
+                        /* Give the slave the chance to stretch the clock */
+                        let mut deadline = Deadline::new(timeout, &clocks);
+                        while !scl.is_input_high() && !deadline.expired() {}
+                        asm::delay(half_period);
+                    }
+
+                    /* Generate a STOP condition, a rising edge on SDA while SCL is high */
+                    scl.set_output_high(false);
+                    asm::delay(half_period);
+                    sda.set_output_high(false);
+                    asm::delay(half_period);
+                    scl.set_output_high(true);
+                    asm::delay(half_period);
+                    sda.set_output_high(true);
+                    asm::delay(half_period);
+
+                    let released = sda.is_input_high() && scl.is_input_high();
+
+                    scl.set_alternate(scl_open_drain);
+                    sda.set_alternate(sda_open_drain);
+                    self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    if released {
+                        Ok(())
+                    } else {
+                        Err(Error::Bus)
+                    }
+                }
+            }
+
+            impl<PINS> I2c<$I2C, PINS> {
+                /// Writes `bytes` to the slave with the 10 bit address `addr`
+                pub fn write_10bit(&mut self, addr: TenBitAddress, bytes: &[u8]) -> Result<(), Error> {
+                    self.write_addr(addr, bytes)
+                }
+
+                /// Reads enough bytes from the slave with the 10 bit address `addr` to fill
+                /// `buffer`
+                pub fn read_10bit(&mut self, addr: TenBitAddress, buffer: &mut [u8]) -> Result<(), Error> {
+                    self.read_addr(addr, buffer)
+                }
+
+                /// Writes `bytes` to the slave with the 10 bit address `addr` and then reads
+                /// enough bytes to fill `buffer` in a single transaction
+                pub fn write_read_10bit(
+                    &mut self,
+                    addr: TenBitAddress,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), Error> {
+                    self.write_read_addr(addr, bytes, buffer)
+                }
+
+                fn write_read_addr<A: Address>(&self, addr: A, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+                    self.set_nbytes(bytes.len());
+                    self.start(addr, false, false);
+
+                    /* Wait until the transmit buffer is empty or all bytes have been sent, a NACK
+                     * aborts the transfer */
+                    self.wait(|isr| isr.txis().bit_is_set() || isr.tc().bit_is_set())?;
+
+                    self.write_bytes(bytes, 0, bytes.len())?;
+
+                    /* Wait until data was sent */
+                    self.wait(|isr| isr.tc().bit_is_set())?;
+
+                    /* Restart for reading and end the transfer with a STOP afterwards */
+                    self.set_nbytes(buffer.len());
+                    self.start(addr, true, true);
+
+                    /* Read in all bytes */
+                    let len = buffer.len();
+                    self.read_bytes(buffer, 0, len)?;
+
+                    self.wait_stop()
+                }
+
+                fn write_addr<A: Address>(&self, addr: A, bytes: &[u8]) -> Result<(), Error> {
+                    self.set_nbytes(bytes.len());
+                    self.start(addr, false, true);
+
+                    self.write_bytes(bytes, 0, bytes.len())?;
+
+                    self.wait_stop()
+                }
+
+                fn read_addr<A: Address>(&self, addr: A, buffer: &mut [u8]) -> Result<(), Error> {
+                    self.set_nbytes(buffer.len());
+                    self.start(addr, true, true);
+
+                    let len = buffer.len();
+                    self.read_bytes(buffer, 0, len)?;
+
+                    self.wait_stop()
+                }
+            }
+
+            impl<PINS> WriteRead for I2c<$I2C, PINS> {
+                type Error = Error;
+
+                fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+                    self.write_read_addr(addr, bytes, buffer)
+                }
+            }
+
             impl<PINS> Write for I2c<$I2C, PINS> {
                 type Error = Error;
 
                 fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Error> {
-                    /* Set up current address, we're trying a "read" command and not going to set anything
-                     * and make sure we end a non-NACKed read (i.e. if we found a device) properly */
-                    self.i2c.cr2.modify(|_, w| {
-                        w.sadd()
-                         .bits(u16::from(addr) << 1)
-                         .nbytes()
-                         .bits(bytes.len() as u8)
-                         .rd_wrn()
-                         .clear_bit()
-                         .autoend()
-                         .set_bit()
-                    });
+                    self.write_addr(addr, bytes)
+                }
+            }
 
-                    /* Send a START condition */
-                    self.i2c.cr2.modify(|_, w| w.start().set_bit());
+            impl<PINS> Read for I2c<$I2C, PINS> {
+                type Error = Error;
 
-                    for c in bytes {
-                        self.send_byte(*c)?;
+                fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Error> {
+                    self.read_addr(addr, buffer)
+                }
+            }
+
+            impl<PINS> WriteIter for I2c<$I2C, PINS> {
+                type Error = Error;
+
+                fn write<B>(&mut self, addr: u8, bytes: B) -> Result<(), Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_iter(addr, bytes, true)?;
+
+                    self.wait_stop()
+                }
+            }
+
+            impl<PINS> WriteIterRead for I2c<$I2C, PINS> {
+                type Error = Error;
+
+                fn write_iter_read<B>(&mut self, addr: u8, bytes: B, buffer: &mut [u8]) -> Result<(), Error>
+                where
+                    B: IntoIterator<Item = u8>,
+                {
+                    self.write_iter(addr, bytes, false)?;
+
+                    /* Wait until data was sent */
+                    self.wait(|isr| isr.tc().bit_is_set())?;
+
+                    self.set_nbytes(buffer.len());
+                    self.start(addr, true, true);
+
+                    let len = buffer.len();
+                    self.read_bytes(buffer, 0, len)?;
+
+                    self.wait_stop()
+                }
+            }
+
+            impl<PINS> Transactional for I2c<$I2C, PINS> {
+                type Error = Error;
+
+                /// Runs adjacent operations of the same direction as one transfer, a change of
+                /// direction results in a repeated START
+                fn exec<'a>(&mut self, addr: u8, operations: &mut [Operation<'a>]) -> Result<(), Error> {
+                    let mut first = 0;
+                    while first < operations.len() {
+                        let read = is_read(&operations[first]);
+                        let last = operations[first..]
+                            .iter()
+                            .position(|op| is_read(op) != read)
+                            .map_or(operations.len(), |n| first + n);
+                        let autoend = last == operations.len();
+
+                        let len = operations[first..last].iter().map(operation_len).sum();
+                        self.set_nbytes(len);
+                        self.start(addr, read, autoend);
+
+                        let mut done = 0;
+                        for op in &mut operations[first..last] {
+                            done = match *op {
+                                Operation::Read(ref mut buffer) => self.read_bytes(buffer, done, len)?,
+                                Operation::Write(bytes) => self.write_bytes(bytes, done, len)?,
+                            };
+                        }
+
+                        if autoend {
+                            self.wait_stop()?;
+                        } else {
+                            self.wait(|isr| isr.tc().bit_is_set())?;
+                        }
+
+                        first = last;
                     }
 
-                    /* Fallthrough is success */
-                    self.i2c
-                        .icr
-                        .write(|w| w.stopcf().set_bit().nackcf().set_bit());
                     Ok(())
                 }
             }
 
+            impl<'a, PINS> Iterator for Scan<'a, $I2C, PINS> {
+                type Item = Result<u8, Error>;
+
+                fn next(&mut self) -> Option<Result<u8, Error>> {
+                    /* 0x78 and above are reserved for 10 bit addressing and future purposes */
+                    while self.next < 0x78 {
+                        let addr = self.next;
+                        self.next += 1;
+
+                        match self.i2c.probe(addr) {
+                            Ok(true) => return Some(Ok(addr)),
+                            Ok(false) => (),
+                            Err(error) => return Some(Err(error)),
+                        }
+                    }
+
+                    None
+                }
+            }
+
+            impl<PINS> I2cSlave<$I2C, PINS> {
+                /// Configures the I2C peripheral as a slave answering to the 7 bit `address`
+                ///
+                /// The setup and hold times are chosen for a master clocking the bus with up to
+                /// the speed of `config`.
+                pub fn $i2c<C>(i2c: $I2C, pins: PINS, address: u8, config: C, clocks: Clocks) -> Self
+                where
+                    PINS: Pins<$I2C>,
+                    C: Into<Config>,
+                {
+                    I2c::<$I2C, PINS>::reset(&i2c, &config.into(), clocks);
+
+                    /* Own address 1 in 7 bit mode, the clock is stretched until we're ready */
+                    i2c.oar1.write(|w| w.oa1().bits(u16::from(address) << 1).oa1en().set_bit());
+
+                    /* Enable the I2C processing */
+                    i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+                    I2cSlave {
+                        i2c,
+                        pins,
+                        timeout: DEFAULT_TIMEOUT,
+                        clocks,
+                    }
+                }
+
+                pub fn release(self) -> ($I2C, PINS) {
+                    (self.i2c, self.pins)
+                }
+
+                /// Sets the time without progress after which a stuck transfer is aborted with
+                /// `Error::Timeout`
+                pub fn set_timeout<T: Into<MicroSeconds>>(&mut self, timeout: T) {
+                    self.timeout = timeout.into().0;
+                }
+
+                /// Additionally answers to the 7 bit `address`, ignoring the `mask` lowest bits
+                /// of it (0 to 7). Panics if `mask` is out of range.
+                pub fn set_address2(&mut self, address: u8, mask: u8) {
+                    assert!(mask <= 7);
+
+                    /* OAR2 can only be changed while it's disabled */
+                    self.i2c.oar2.modify(|_, w| w.oa2en().clear_bit());
+                    self.i2c.oar2.write(|w| {
+                        w.oa2()
+                         .bits(address)
+                         .oa2msk()
+                         .bits(mask)
+                         .oa2en()
+                         .set_bit()
+                    });
+                }
+
+                /// Stops answering to the second own address
+                pub fn disable_address2(&mut self) {
+                    self.i2c.oar2.modify(|_, w| w.oa2en().clear_bit());
+                }
+
+                /// Starts listening for an `event`
+                pub fn listen(&mut self, event: Event) {
+                    self.set_interrupt(event, true);
+                }
+
+                /// Stops listening for an `event`
+                pub fn unlisten(&mut self, event: Event) {
+                    self.set_interrupt(event, false);
+                }
+
+                fn set_interrupt(&mut self, event: Event, enable: bool) {
+                    self.i2c.cr1.modify(|_, w| match event {
+                        Event::AddressMatch => w.addrie().bit(enable),
+                        Event::Stop => w.stopie().bit(enable),
+                        Event::Nack => w.nackie().bit(enable),
+                        Event::Rxne => w.rxie().bit(enable),
+                        Event::Txis => w.txie().bit(enable),
+                        Event::Error => w.errie().bit(enable),
+                    });
+                }
+
+                /// Returns the next bus event
+                ///
+                /// After an address match the clock is stretched until the request is served
+                /// with `read` or `write`, until then the same event is returned again.
+                pub fn poll(&mut self) -> nb::Result<SlaveEvent, Error> {
+                    let isr = self.i2c.isr.read();
+
+                    if let Some(error) = bus_error(&isr) {
+                        software_reset(&self.i2c);
+                        clear_flags(&self.i2c);
+                        Err(nb::Error::Other(error))
+                    } else if isr.addr().bit_is_set() {
+                        let address = isr.addcode().bits();
+                        Ok(if isr.dir().bit_is_set() {
+                            SlaveEvent::ReadRequested { address }
+                        } else {
+                            SlaveEvent::WriteRequested { address }
+                        })
+                    } else if isr.stopf().bit_is_set() {
+                        self.i2c.icr.write(|w| w.stopcf().set_bit().nackcf().set_bit());
+                        Ok(SlaveEvent::Stop)
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Receives the data of a master write into `buffer`
+                ///
+                /// Returns the number of bytes stored once the master ends the transfer with a
+                /// STOP or repeated START condition. Bytes not fitting into the buffer are
+                /// acknowledged but dropped.
+                pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+                    /* Release the clock */
+                    self.i2c.icr.write(|w| w.addrcf().set_bit());
+
+                    let mut count = 0;
+                    let mut deadline = Deadline::new(self.timeout, &self.clocks);
+                    loop {
+                        let isr = self.i2c.isr.read();
+
+                        if let Some(error) = bus_error(&isr) {
+                            software_reset(&self.i2c);
+                            clear_flags(&self.i2c);
+                            return Err(error);
+                        } else if isr.rxne().bit_is_set() {
+                            let byte = self.i2c.rxdr.read().bits() as u8;
+                            if let Some(slot) = buffer.get_mut(count) {
+                                *slot = byte;
+                                count += 1;
+                            }
+                            deadline = Deadline::new(self.timeout, &self.clocks);
+                        } else if isr.stopf().bit_is_set() || isr.addr().bit_is_set() {
+                            /* Leave the flags for `poll` to report */
+                            return Ok(count);
+                        } else if deadline.expired() {
+                            software_reset(&self.i2c);
+                            clear_flags(&self.i2c);
+                            return Err(Error::Timeout);
+                        }
+                    }
+                }
+
+                /// Sends `bytes` to a reading master
+                ///
+                /// Returns the number of bytes sent once the master stops reading. If the master
+                /// reads more than `bytes` holds, 0xFF is sent instead.
+                pub fn write(&mut self, bytes: &[u8]) -> Result<usize, Error> {
+                    /* Flush stale data and release the clock */
+                    self.i2c.isr.write(|w| w.txe().set_bit());
+                    self.i2c.icr.write(|w| w.addrcf().set_bit());
+
+                    let mut count = 0;
+                    let mut deadline = Deadline::new(self.timeout, &self.clocks);
+                    loop {
+                        let isr = self.i2c.isr.read();
+
+                        if let Some(error) = bus_error(&isr) {
+                            software_reset(&self.i2c);
+                            clear_flags(&self.i2c);
+                            return Err(error);
+                        } else if isr.nackf().bit_is_set() {
+                            /* The master NACKs the last byte it wants to read */
+                            self.i2c.icr.write(|w| w.nackcf().set_bit());
+                            if self.i2c.isr.read().txe().bit_is_clear() {
+                                /* The byte preloaded for the next transfer was never sent */
+                                count -= 1;
+                                self.i2c.isr.write(|w| w.txe().set_bit());
+                            }
+                            return Ok(cmp::min(count, bytes.len()));
+                        } else if isr.txis().bit_is_set() {
+                            let byte = bytes.get(count).cloned().unwrap_or(0xFF);
+                            self.i2c.txdr.write(|w| unsafe { w.bits(u32::from(byte)) });
+                            count += 1;
+                            deadline = Deadline::new(self.timeout, &self.clocks);
+                        } else if isr.stopf().bit_is_set() || isr.addr().bit_is_set() {
+                            return Ok(cmp::min(count, bytes.len()));
+                        } else if deadline.expired() {
+                            software_reset(&self.i2c);
+                            clear_flags(&self.i2c);
+                            return Err(Error::Timeout);
+                        }
+                    }
+                }
+            }
         )+
     }
 }
@@ -257,7 +1298,7 @@ macro_rules! i2c {
     feature = "stm32f030rc"
 ))]
 i2c! {
-    I2C1: (i2c1, i2c1en, i2c1rst, apb1enr, apb1rstr),
+    I2C1: (i2c1, i2c1en, i2c1rst, apb1enr, apb1rstr, i2c1clk, i2c1_fm_plus),
 }
 #[cfg(any(
     feature = "stm32f030c8",
@@ -266,5 +1307,259 @@ i2c! {
     feature = "stm32f030rc"
 ))]
 i2c! {
-    I2C2: (i2c2, i2c2en, i2c2rst, apb1enr, apb1rstr),
+    I2C2: (i2c2, i2c2en, i2c2rst, apb1enr, apb1rstr, pclk, i2c2_fm_plus),
+}
+
+impl<PINS> Smbus<PINS> {
+    /// Configures I2C1 as SMBus host
+    ///
+    /// To get notified by slaves, connect SMBA (PB5 in AF3) and use `enable_alert`.
+    pub fn i2c1<C>(i2c: I2C1, pins: PINS, config: C, clocks: Clocks) -> Self
+    where
+        PINS: Pins<I2C1>,
+        C: Into<Config>,
+    {
+        let i2c = I2c::i2c1(i2c, pins, config, clocks);
+
+        /* Timeouts are counted in periods of 2048 I2C clock cycles. The clock low timeout is
+         * rounded up to at least 25ms, the clock extension timeout down to at most 10ms. */
+        let cycles = |ms: u32| u64::from(clocks.i2c1clk().0) * u64::from(ms) / 1000;
+        let timeouta = cmp::min(cycles(25).div_ceil(2048).saturating_sub(1), 0xFFF) as u16;
+        let timeoutb = cmp::min((cycles(10) / 2048).saturating_sub(1), 0xFFF) as u16;
+
+        i2c.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+        i2c.i2c.cr1.modify(|_, w| w.pecen().set_bit());
+
+        /* Detect a clock held low for more than 25ms and a cumulative clock extension of more
+         * than 10ms per byte as required for SMBus masters */
+        i2c.i2c.timeoutr.write(|w| {
+            w.timeouta()
+             .bits(timeouta)
+             .tidle()
+             .clear_bit()
+             .timouten()
+             .set_bit()
+             .timeoutb()
+             .bits(timeoutb)
+             .texten()
+             .set_bit()
+        });
+        i2c.i2c.cr1.modify(|_, w| w.pe().set_bit());
+
+        Smbus { i2c }
+    }
+
+    pub fn release(self) -> (I2C1, PINS) {
+        self.i2c.release()
+    }
+
+    /// Enables the SMBA input, a slave pulling it low is reported by `is_alert_pending`
+    pub fn enable_alert(&mut self) {
+        self.set_alert(true);
+    }
+
+    pub fn disable_alert(&mut self) {
+        self.set_alert(false);
+    }
+
+    fn set_alert(&mut self, enable: bool) {
+        let i2c = &self.i2c.i2c;
+        /* ALERTEN can only be changed while the peripheral is disabled */
+        i2c.cr1.modify(|_, w| w.pe().clear_bit());
+        i2c.cr1.modify(|_, w| w.alerten().bit(enable));
+        i2c.cr1.modify(|_, w| w.pe().set_bit());
+    }
+
+    /// Returns whether a slave signalled an alert on SMBA
+    pub fn is_alert_pending(&self) -> bool {
+        self.i2c.i2c.isr.read().alert().bit_is_set()
+    }
+
+    pub fn clear_alert(&mut self) {
+        self.i2c.i2c.icr.write(|w| w.alertcf().set_bit());
+    }
+
+    /// Returns whether a device acknowledges `addr`
+    pub fn probe(&mut self, addr: u8) -> Result<bool, Error> {
+        self.i2c.probe(addr)
+    }
+
+    /// Sends the "Write Byte" command
+    pub fn write_byte_data(&mut self, addr: u8, command: u8, value: u8) -> Result<(), Error> {
+        self.write(addr, &[command], &[value])
+    }
+
+    /// Sends the "Read Byte" command
+    pub fn read_byte_data(&mut self, addr: u8, command: u8) -> Result<u8, Error> {
+        let mut value = [0];
+        self.read(addr, command, &mut value)?;
+        Ok(value[0])
+    }
+
+    /// Sends the "Write Word" command, the low byte is sent first
+    pub fn write_word_data(&mut self, addr: u8, command: u8, value: u16) -> Result<(), Error> {
+        self.write(addr, &[command], &[value as u8, (value >> 8) as u8])
+    }
+
+    /// Sends the "Read Word" command
+    pub fn read_word_data(&mut self, addr: u8, command: u8) -> Result<u16, Error> {
+        let mut value = [0; 2];
+        self.read(addr, command, &mut value)?;
+        Ok(u16::from(value[0]) | u16::from(value[1]) << 8)
+    }
+
+    /// Sends the "Block Write" command with up to 255 `bytes`
+    pub fn write_block_data(&mut self, addr: u8, command: u8, bytes: &[u8]) -> Result<(), Error> {
+        assert!(bytes.len() <= 255);
+        self.write(addr, &[command, bytes.len() as u8], bytes)
+    }
+
+    /// Sends the "Block Read" command and returns the number of bytes the slave sent
+    ///
+    /// Bytes not fitting into `buffer` are dropped.
+    pub fn read_block_data(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<usize, Error> {
+        let i2c = &self.i2c;
+
+        i2c.set_nbytes(1);
+        i2c.start(addr, false, false);
+        i2c.send_byte(command)?;
+        i2c.wait(|isr| isr.tc().bit_is_set())?;
+
+        /* The length is only known after the first byte, so the transfer is reloaded */
+        i2c.i2c.cr2.modify(|_, w| w.nbytes().bits(1).reload().set_bit());
+        i2c.start(addr, true, true);
+        let count = usize::from(i2c.recv_byte()?);
+        i2c.wait(|isr| isr.tcr().bit_is_set())?;
+
+        /* Followed by the PEC byte */
+        let len = count + 1;
+        i2c.set_nbytes(len);
+        i2c.i2c.cr2.modify(|_, w| w.pecbyte().set_bit());
+
+        for i in 0..len {
+            i2c.reload(i, len)?;
+            let byte = i2c.recv_byte()?;
+            if i < count {
+                if let Some(slot) = buffer.get_mut(i) {
+                    *slot = byte;
+                }
+            }
+        }
+
+        i2c.wait_stop()?;
+        Ok(count)
+    }
+
+    /// Writes `header` and `bytes` followed by the PEC
+    fn write(&mut self, addr: u8, header: &[u8], bytes: &[u8]) -> Result<(), Error> {
+        let i2c = &self.i2c;
+        let len = header.len() + bytes.len() + 1;
+
+        i2c.set_nbytes(len);
+        i2c.i2c.cr2.modify(|_, w| w.pecbyte().set_bit());
+        i2c.start(addr, false, true);
+
+        let done = i2c.write_bytes(header, 0, len)?;
+        i2c.write_bytes(bytes, done, len)?;
+
+        i2c.wait_stop()
+    }
+
+    /// Writes `command` and reads `buffer` followed by the PEC
+    fn read(&mut self, addr: u8, command: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let i2c = &self.i2c;
+
+        i2c.set_nbytes(1);
+        i2c.start(addr, false, false);
+        i2c.send_byte(command)?;
+        i2c.wait(|isr| isr.tc().bit_is_set())?;
+
+        let len = buffer.len() + 1;
+        i2c.set_nbytes(len);
+        i2c.i2c.cr2.modify(|_, w| w.pecbyte().set_bit());
+        i2c.start(addr, true, true);
+
+        let done = i2c.read_bytes(buffer, 0, len)?;
+        i2c.reload(done, len)?;
+        i2c.recv_byte()?;
+
+        i2c.wait_stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Address, Config, ModeTimings, TenBitAddress, Timing, ANALOG_FILTER_MAX, ANALOG_FILTER_MIN,
+        FAST_MODE, FAST_MODE_PLUS, STANDARD_MODE,
+    };
+    use core::cmp;
+    use time::U32Ext;
+
+    /* The values differ from the examples of the reference manual, which don't always use the
+     * smallest prescaler, so the timings are checked against the I2C specification instead */
+    fn check(i2cclk: u32, speed: u32, mode: &ModeTimings) {
+        let timing = Timing::new(i2cclk.hz(), &Config::new(speed.khz())).unwrap();
+
+        let freq = u64::from(i2cclk);
+        let presc = u64::from(timing.presc) + 1;
+        let at_least = |cycles: u64, ns: u32| cycles * 1_000_000_000 >= u64::from(ns) * freq;
+        let at_most = |cycles: u64, ns: u32| cycles * 1_000_000_000 <= u64::from(ns) * freq;
+
+        // The SCL low and high periods are extended by at least the analog filter delay and
+        // two I2C clock cycles
+        let sync = u64::from(ANALOG_FILTER_MIN) * freq / 1_000_000_000 + 2;
+        let low = (u64::from(timing.scll) + 1) * presc + sync;
+        let high = (u64::from(timing.sclh) + 1) * presc + sync;
+        assert!(at_least(low, mode.low), "tLOW too short");
+        assert!(at_least(high, mode.high), "tHIGH too short");
+        assert!((low + high) * u64::from(speed) * 1000 >= freq, "SCL too fast");
+
+        // tSCLDEL >= tr + tSU;DAT
+        let scldel = (u64::from(timing.scldel) + 1) * presc;
+        assert!(at_least(scldel, mode.rise + mode.setup), "tSCLDEL too short");
+
+        // tSDADEL >= tf - tAF(min) - 3 * tI2CCLK
+        // tSDADEL <= tHD;DAT(max) - tr - tAF(max) - 4 * tI2CCLK, unless the filter delays
+        // leave no room for the minimum
+        let sdadel = u64::from(timing.sdadel) * presc;
+        let sdadel_min = (u64::from(mode.fall - ANALOG_FILTER_MIN) * freq + 999_999_999)
+            / 1_000_000_000;
+        let hold_max = mode.hold - mode.rise - ANALOG_FILTER_MAX;
+        assert!(sdadel + 3 >= sdadel_min, "tSDADEL too short");
+        assert!(
+            !at_most(cmp::max(sdadel_min, 3) + 1, hold_max) || at_most(sdadel + 4, hold_max),
+            "tSDADEL too long"
+        );
+    }
+
+    #[test]
+    fn hsi() {
+        check(8_000_000, 10, &STANDARD_MODE);
+        check(8_000_000, 100, &STANDARD_MODE);
+        check(8_000_000, 400, &FAST_MODE);
+    }
+
+    #[test]
+    fn sysclk_48mhz() {
+        check(48_000_000, 100, &STANDARD_MODE);
+        check(48_000_000, 400, &FAST_MODE);
+        check(48_000_000, 1000, &FAST_MODE_PLUS);
+    }
+
+    #[test]
+    fn unreachable() {
+        let timing = |i2cclk: u32, speed: u32| Timing::new(i2cclk.hz(), &Config::new(speed.khz()));
+
+        // Fm+ needs a faster I2C clock than HSI, more than 1 MHz isn't supported at all
+        assert_eq!(timing(8_000_000, 1000), None);
+        assert_eq!(timing(48_000_000, 1001), None);
+        assert_eq!(timing(48_000_000, 0), None);
+    }
+
+    #[test]
+    fn ten_bit_address() {
+        assert_eq!(TenBitAddress::new(0x3FF).map(Address::sadd), Some(0x3FF));
+        assert_eq!(TenBitAddress::new(0x400), None);
+    }
 }
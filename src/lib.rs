@@ -17,12 +17,20 @@ pub extern crate stm32f0;
 pub use stm32f0::interrupt;
 pub use stm32f0::stm32f0x0 as stm32;
 
+#[macro_use]
+mod macros;
+
+pub mod capture;
+pub mod counter;
 pub mod delay;
 pub mod gpio;
 pub mod i2c;
 pub mod prelude;
+pub mod pwm;
+pub mod qei;
 pub mod rcc;
 pub mod serial;
 pub mod spi;
+pub mod sync;
 pub mod time;
 pub mod timers;
@@ -0,0 +1,41 @@
+/// Enables and resets a peripheral to a clean slate state
+macro_rules! enable_reset {
+    ($apbenr:ident, $apbrstr:ident, $en:ident, $rst:ident) => {{
+        // NOTE(unsafe) This executes only during initialisation
+        let rcc = unsafe { &(*$crate::stm32::RCC::ptr()) };
+        rcc.$apbenr.modify(|_, w| w.$en().set_bit());
+        rcc.$apbrstr.modify(|_, w| w.$rst().set_bit());
+        rcc.$apbrstr.modify(|_, w| w.$rst().clear_bit());
+    }};
+}
+
+/// Extends the 16 bit counter of the timer `self.tim` to 64 bits by counting its overflows in
+/// `self.overflows`
+macro_rules! overflow_counter {
+    () => {
+        /// Counts a pending counter overflow, returns whether there was one
+        pub fn on_overflow(&mut self) -> bool {
+            if self.tim.sr.read().uif().bit_is_set() {
+                /* The flags are cleared by writing 0, writing 1 has no effect */
+                self.tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0001) });
+                self.overflows += 1;
+                true
+            } else {
+                false
+            }
+        }
+
+        /// Returns the counter value extended to 64 bits
+        fn extended_count(&mut self) -> u64 {
+            loop {
+                let count = self.tim.cnt.read().bits() & 0xFFFF;
+
+                /* An overflow may have happened after reading the counter, count it and read
+                 * again */
+                if !self.on_overflow() {
+                    return self.overflows << 16 | u64::from(count);
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,861 @@
+use core::cmp;
+use core::marker::PhantomData;
+
+use stm32::{TIM1, TIM14, TIM15, TIM16, TIM17, TIM3};
+
+use cast::u32;
+use hal;
+use nb;
+use rcc::Clocks;
+use void::Void;
+
+use capture::InputPins;
+use gpio::*;
+use time::Hertz;
+use timers::{self, Error as TimerError, Timeout};
+
+/// PWM abstraction for a whole timer, `BKIN` is the break input pin of TIM1 while it's enabled
+pub struct Pwm<TIM, PINS, BKIN = ()> {
+    pub(crate) tim: TIM,
+    pins: PINS,
+    bkin: BKIN,
+    // CCxNE bits of the channels with a complementary pin
+    ccne: u32,
+    clocks: Clocks,
+}
+
+/// Timer and pins of a `Pwm` split into channels, see `Pwm::split`
+pub struct SplitPwm<TIM, PINS, BKIN = ()> {
+    pwm: Pwm<TIM, PINS, BKIN>,
+}
+
+/// PWM error
+#[derive(Debug)]
+pub enum Error {
+    /// The dead time is longer than the timer can insert
+    DeadTimeTooLong,
+}
+
+/// Single PWM channel, obtained by splitting a `Pwm`
+pub struct PwmChannel<TIM, CHANNEL> {
+    // CCxNE bits of the channels with a complementary pin
+    ccne: u32,
+    _tim: PhantomData<TIM>,
+    _channel: PhantomData<CHANNEL>,
+}
+
+/// Single pulse output on channel 1, delayed from the trigger
+pub struct OnePulse<TIM, PIN> {
+    tim: TIM,
+    pin: PIN,
+    clocks: Clocks,
+}
+
+/// Capture/compare channel 1 (type state)
+pub struct C1;
+/// Capture/compare channel 2 (type state)
+pub struct C2;
+/// Capture/compare channel 3 (type state)
+pub struct C3;
+/// Capture/compare channel 4 (type state)
+pub struct C4;
+
+/// Channels of a timer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    C1,
+    C2,
+    C3,
+    C4,
+}
+
+/// Pin of capture/compare channel 1
+pub trait PinC1<TIM> {}
+/// Pin of capture/compare channel 2
+pub trait PinC2<TIM> {}
+/// Pin of capture/compare channel 3
+pub trait PinC3<TIM> {}
+/// Pin of capture/compare channel 4
+pub trait PinC4<TIM> {}
+
+/// Complementary output pin of capture/compare channel 1
+pub trait PinC1N<TIM> {}
+/// Complementary output pin of capture/compare channel 2
+pub trait PinC2N<TIM> {}
+/// Complementary output pin of capture/compare channel 3
+pub trait PinC3N<TIM> {}
+
+/// Break input pin
+pub trait BkinPin<TIM> {}
+
+/// PWM output of capture/compare channel 1, its pin or a pair of it and the complementary pin
+pub trait OutputC1<TIM> {
+    #[doc(hidden)]
+    const CCNE: u32 = 0;
+}
+/// PWM output of capture/compare channel 2, its pin or a pair of it and the complementary pin
+pub trait OutputC2<TIM> {
+    #[doc(hidden)]
+    const CCNE: u32 = 0;
+}
+/// PWM output of capture/compare channel 3, its pin or a pair of it and the complementary pin
+pub trait OutputC3<TIM> {
+    #[doc(hidden)]
+    const CCNE: u32 = 0;
+}
+/// PWM output of capture/compare channel 4, its pin
+pub trait OutputC4<TIM> {
+    #[doc(hidden)]
+    const CCNE: u32 = 0;
+}
+
+impl<TIM, P: PinC1<TIM>> OutputC1<TIM> for P {}
+impl<TIM, P: PinC2<TIM>> OutputC2<TIM> for P {}
+impl<TIM, P: PinC3<TIM>> OutputC3<TIM> for P {}
+impl<TIM, P: PinC4<TIM>> OutputC4<TIM> for P {}
+
+// A pair of an output and its complementary output enables the complementary output along with
+// the channel
+impl<P, N> OutputC1<TIM1> for (P, N)
+where
+    P: PinC1<TIM1>,
+    N: PinC1N<TIM1>,
+{
+    const CCNE: u32 = 0x0004;
+}
+impl<P, N> OutputC2<TIM1> for (P, N)
+where
+    P: PinC2<TIM1>,
+    N: PinC2N<TIM1>,
+{
+    const CCNE: u32 = 0x0040;
+}
+impl<P, N> OutputC3<TIM1> for (P, N)
+where
+    P: PinC3<TIM1>,
+    N: PinC3N<TIM1>,
+{
+    const CCNE: u32 = 0x0400;
+}
+
+/// Active level of the break input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BreakPolarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// Write protection of the timer configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LockLevel {
+    /// Protects the dead-time, break and idle state configuration
+    Level1,
+    /// Additionally protects the channel polarities and off-state selections
+    Level2,
+    /// Additionally protects the output compare modes
+    Level3,
+}
+
+/// Set of channel pins of a timer, `CHANNELS` is a tuple of the used channels
+pub trait Pins<TIM, CHANNELS> {
+    const C1: bool = false;
+    const C2: bool = false;
+    const C3: bool = false;
+    const C4: bool = false;
+    #[doc(hidden)]
+    const CCNE: u32;
+    /// Independent PWM channels for the pins
+    type Channels;
+
+    #[doc(hidden)]
+    fn channels() -> Self::Channels;
+}
+
+macro_rules! pins_impl {
+    ($(($($PINX:ident),+), ($($TRAIT:ident),+), ($($INTRAIT:ident),+), ($($ENCHX:ident),+);)+) => {
+        $(
+            #[allow(unused_parens)]
+            impl<TIM, $($PINX,)+> InputPins<TIM, ($($ENCHX),+)> for ($($PINX),+)
+            where
+                $($PINX: $INTRAIT<TIM>,)+
+            {
+                $(const $ENCHX: bool = true;)+
+            }
+
+            #[allow(unused_parens)]
+            impl<TIM, $($PINX,)+> Pins<TIM, ($($ENCHX),+)> for ($($PINX),+)
+            where
+                $($PINX: $TRAIT<TIM>,)+
+            {
+                $(const $ENCHX: bool = true;)+
+                const CCNE: u32 = 0 $(| <$PINX as $TRAIT<TIM>>::CCNE)+;
+                type Channels = ($(PwmChannel<TIM, $ENCHX>),+);
+
+                fn channels() -> Self::Channels {
+                    ($(PwmChannel::<TIM, $ENCHX> {
+                        ccne: Self::CCNE,
+                        _tim: PhantomData,
+                        _channel: PhantomData,
+                    }),+)
+                }
+            }
+        )+
+    };
+}
+
+pins_impl!(
+    (P1, P2, P3, P4), (OutputC1, OutputC2, OutputC3, OutputC4),
+        (PinC1, PinC2, PinC3, PinC4), (C1, C2, C3, C4);
+    (P2, P3, P4), (OutputC2, OutputC3, OutputC4), (PinC2, PinC3, PinC4), (C2, C3, C4);
+    (P1, P3, P4), (OutputC1, OutputC3, OutputC4), (PinC1, PinC3, PinC4), (C1, C3, C4);
+    (P1, P2, P4), (OutputC1, OutputC2, OutputC4), (PinC1, PinC2, PinC4), (C1, C2, C4);
+    (P1, P2, P3), (OutputC1, OutputC2, OutputC3), (PinC1, PinC2, PinC3), (C1, C2, C3);
+    (P3, P4), (OutputC3, OutputC4), (PinC3, PinC4), (C3, C4);
+    (P2, P4), (OutputC2, OutputC4), (PinC2, PinC4), (C2, C4);
+    (P2, P3), (OutputC2, OutputC3), (PinC2, PinC3), (C2, C3);
+    (P1, P4), (OutputC1, OutputC4), (PinC1, PinC4), (C1, C4);
+    (P1, P3), (OutputC1, OutputC3), (PinC1, PinC3), (C1, C3);
+    (P1, P2), (OutputC1, OutputC2), (PinC1, PinC2), (C1, C2);
+    (P1), (OutputC1), (PinC1), (C1);
+    (P2), (OutputC2), (PinC2), (C2);
+    (P3), (OutputC3), (PinC3), (C3);
+    (P4), (OutputC4), (PinC4), (C4);
+);
+
+macro_rules! channel_pins {
+    ($($TIM:ident: $PINC:ident for [$($PIN:ty),+],)+) => {
+        $(
+            $(
+                impl $PINC<$TIM> for $PIN {}
+            )+
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+channel_pins! {
+    TIM1: PinC2 for [gpioa::PA9<Alternate<AF2>>],
+    TIM1: PinC3 for [gpioa::PA10<Alternate<AF2>>],
+    TIM3: PinC1 for [gpioa::PA6<Alternate<AF1>>],
+    TIM3: PinC2 for [gpioa::PA7<Alternate<AF1>>],
+    TIM14: PinC1 for [gpioa::PA4<Alternate<AF4>>, gpioa::PA7<Alternate<AF4>>],
+    TIM16: PinC1 for [gpioa::PA6<Alternate<AF5>>],
+    TIM17: PinC1 for [gpioa::PA7<Alternate<AF5>>],
+    TIM1: PinC1N for [gpioa::PA7<Alternate<AF2>>],
+    TIM1: BkinPin for [gpioa::PA6<Alternate<AF2>>],
+    TIM3: PinC4 for [gpiob::PB1<Alternate<AF1>>],
+    TIM14: PinC1 for [gpiob::PB1<Alternate<AF0>>],
+    TIM1: PinC3N for [gpiob::PB1<Alternate<AF2>>],
+}
+
+#[cfg(any(
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+channel_pins! {
+    TIM1: PinC1 for [gpioa::PA8<Alternate<AF2>>],
+    TIM1: PinC4 for [gpioa::PA11<Alternate<AF2>>],
+    TIM3: PinC1 for [gpiob::PB4<Alternate<AF1>>],
+    TIM3: PinC2 for [gpiob::PB5<Alternate<AF1>>],
+    TIM3: PinC3 for [gpiob::PB0<Alternate<AF1>>],
+    TIM1: PinC2N for [gpiob::PB0<Alternate<AF2>>],
+}
+
+#[cfg(any(
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+channel_pins! {
+    TIM16: PinC1 for [gpiob::PB8<Alternate<AF2>>],
+    TIM17: PinC1 for [gpiob::PB9<Alternate<AF2>>],
+    TIM1: PinC1N for [gpiob::PB13<Alternate<AF2>>],
+    TIM1: PinC2N for [gpiob::PB14<Alternate<AF2>>],
+    TIM1: PinC3N for [gpiob::PB15<Alternate<AF2>>],
+    TIM1: BkinPin for [gpiob::PB12<Alternate<AF2>>],
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+channel_pins! {
+    TIM15: PinC1 for [gpioa::PA2<Alternate<AF0>>, gpiob::PB14<Alternate<AF1>>],
+    TIM15: PinC2 for [gpioa::PA3<Alternate<AF0>>, gpiob::PB15<Alternate<AF1>>],
+}
+
+#[cfg(any(feature = "stm32f030r8", feature = "stm32f030rc"))]
+channel_pins! {
+    TIM3: PinC1 for [gpioc::PC6<Alternate<AF0>>],
+    TIM3: PinC2 for [gpioc::PC7<Alternate<AF0>>],
+    TIM3: PinC3 for [gpioc::PC8<Alternate<AF0>>],
+    TIM3: PinC4 for [gpioc::PC9<Alternate<AF0>>],
+}
+
+/// Returns the prescaler and auto-reload values for an update frequency `freq` with the timer
+/// clock `clk`
+fn psc_arr(clk: Hertz, freq: Hertz) -> (u16, u16) {
+    assert!(freq.0 > 0);
+
+    /* The maximum duty cycle of ARR + 1 has to fit into 16 bits */
+    timers::psc_arr(u64::from(clk.0 / freq.0), 0xFFFE).expect("PWM frequency out of range")
+}
+
+// Timers with a break input gate all their outputs with the main output enable (MOE)
+trait MainOutput {
+    fn enable_main_output(&self) {}
+}
+
+macro_rules! main_output {
+    ($($TIM:ident),+) => {
+        $(
+            impl MainOutput for $TIM {
+                fn enable_main_output(&self) {
+                    self.bdtr.modify(|_, w| w.moe().set_bit());
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl MainOutput for TIM3 {}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl MainOutput for TIM14 {}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+main_output!(TIM1, TIM16, TIM17);
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+main_output!(TIM15);
+
+macro_rules! pwm {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident) [
+        $($C:ident: ($ch:expr, $ccrX:ident, $ccmrX_output:ident, $ccxe:expr),)+
+    ],)+) => {
+        $(
+            impl<PINS> Pwm<$TIM, PINS> {
+                /// Configures the timer for PWM output with a frequency of `freq` on the
+                /// channels of `pins`
+                ///
+                /// All channels start disabled with a duty cycle of 0.
+                pub fn $tim<CHANNELS, F>(tim: $TIM, pins: PINS, freq: F, clocks: Clocks) -> Self
+                where
+                    PINS: Pins<$TIM, CHANNELS>,
+                    F: Into<Hertz>,
+                {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    $(
+                        if PINS::$C {
+                            /* PWM mode 1 with preloaded compare value */
+                            let offset = 8 * (($ch - 1) % 2);
+                            tim.$ccmrX_output.modify(|r, w| unsafe {
+                                w.bits((r.bits() & !(0xFF << offset)) | (0b0110_1000 << offset))
+                            });
+                        }
+                    )+
+
+                    let mut pwm = Pwm {
+                        tim,
+                        pins,
+                        bkin: (),
+                        ccne: PINS::CCNE,
+                        clocks,
+                    };
+                    hal::Pwm::set_period(&mut pwm, freq);
+                    pwm.tim.enable_main_output();
+
+                    /* Preload the auto-reload value and start counting */
+                    pwm.tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+
+                    pwm
+                }
+
+                /// Stops the timer and releases the TIM peripheral and pins
+                pub fn release(self) -> ($TIM, PINS) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pins)
+                }
+            }
+
+            impl<PINS, BKIN> Pwm<$TIM, PINS, BKIN> {
+                /// Splits the timer into independent PWM channels
+                ///
+                /// The timer and pins stay in the `SplitPwm`, which gives back the `Pwm` when
+                /// joined with the channels.
+                pub fn split<CHANNELS>(self) -> (PINS::Channels, SplitPwm<$TIM, PINS, BKIN>)
+                where
+                    PINS: Pins<$TIM, CHANNELS>,
+                {
+                    (PINS::channels(), SplitPwm { pwm: self })
+                }
+
+                /// Stops the counter and resets it to 0
+                pub fn stop(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim.cnt.reset();
+                }
+
+                /// Starts the counter
+                pub fn start(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+            }
+
+            impl<PINS, BKIN> SplitPwm<$TIM, PINS, BKIN> {
+                /// Joins the `channels` obtained by `split` back into the `Pwm`
+                pub fn join<CHANNELS>(self, _channels: PINS::Channels) -> Pwm<$TIM, PINS, BKIN>
+                where
+                    PINS: Pins<$TIM, CHANNELS>,
+                {
+                    self.pwm
+                }
+            }
+
+            impl<PINS, BKIN> hal::Pwm for Pwm<$TIM, PINS, BKIN> {
+                type Channel = Channel;
+                type Time = Hertz;
+                type Duty = u16;
+
+                #[allow(unreachable_patterns)]
+                fn disable(&mut self, channel: Channel) {
+                    match channel {
+                        $(
+                            Channel::$C => {
+                                let mask = $ccxe | (self.ccne & $ccxe << 2);
+                                self.tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+                            }
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                #[allow(unreachable_patterns)]
+                fn enable(&mut self, channel: Channel) {
+                    match channel {
+                        $(
+                            Channel::$C => {
+                                let mask = $ccxe | (self.ccne & $ccxe << 2);
+                                self.tim.ccer.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+                            }
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                fn get_period(&self) -> Hertz {
+                    let psc = self.tim.psc.read().bits() + 1;
+                    let arr = self.tim.arr.read().bits() + 1;
+                    Hertz(self.clocks.timclk().0 / psc / arr)
+                }
+
+                #[allow(unreachable_patterns)]
+                fn get_duty(&self, channel: Channel) -> u16 {
+                    match channel {
+                        $(
+                            Channel::$C => self.tim.$ccrX.read().bits() as u16,
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                fn get_max_duty(&self) -> u16 {
+                    self.tim.arr.read().bits() as u16 + 1
+                }
+
+                #[allow(unreachable_patterns)]
+                fn set_duty(&mut self, channel: Channel, duty: u16) {
+                    match channel {
+                        $(
+                            Channel::$C => self.tim.$ccrX.write(|w| unsafe { w.bits(u32(duty)) }),
+                        )+
+                        _ => panic!("timer doesn't have this channel"),
+                    }
+                }
+
+                fn set_period<P>(&mut self, period: P)
+                where
+                    P: Into<Hertz>,
+                {
+                    let (psc, arr) = psc_arr(self.clocks.timclk(), period.into());
+
+                    self.tim.psc.write(|w| unsafe { w.bits(u32(psc)) });
+                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
+
+                    /* Load the new values right away */
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                }
+            }
+
+            $(
+                impl hal::PwmPin for PwmChannel<$TIM, $C> {
+                    type Duty = u16;
+
+                    fn disable(&mut self) {
+                        let mask = $ccxe | (self.ccne & $ccxe << 2);
+                        // NOTE(unsafe) only the enable bits of this channel are changed
+                        unsafe {
+                            (*$TIM::ptr()).ccer.modify(|r, w| w.bits(r.bits() & !mask));
+                        }
+                    }
+
+                    fn enable(&mut self) {
+                        let mask = $ccxe | (self.ccne & $ccxe << 2);
+                        // NOTE(unsafe) only the enable bits of this channel are changed
+                        unsafe {
+                            (*$TIM::ptr()).ccer.modify(|r, w| w.bits(r.bits() | mask));
+                        }
+                    }
+
+                    fn get_duty(&self) -> u16 {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { (*$TIM::ptr()).$ccrX.read().bits() as u16 }
+                    }
+
+                    fn get_max_duty(&self) -> u16 {
+                        // NOTE(unsafe) atomic read with no side effects
+                        unsafe { (*$TIM::ptr()).arr.read().bits() as u16 + 1 }
+                    }
+
+                    fn set_duty(&mut self, duty: u16) {
+                        // NOTE(unsafe) atomic write to this channel's own register
+                        unsafe { (*$TIM::ptr()).$ccrX.write(|w| w.bits(u32(duty))) }
+                    }
+                }
+            )+
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+pwm! {
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output, 0x0001),
+        C2: (2, ccr2, ccmr1_output, 0x0010),
+        C3: (3, ccr3, ccmr2_output, 0x0100),
+        C4: (4, ccr4, ccmr2_output, 0x1000),
+    ],
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr) [
+        C1: (1, ccr1, ccmr1_output, 0x0001),
+        C2: (2, ccr2, ccmr1_output, 0x0010),
+        C3: (3, ccr3, ccmr2_output, 0x0100),
+        C4: (4, ccr4, ccmr2_output, 0x1000),
+    ],
+    TIM14: (tim14, tim14en, tim14rst, apb1enr, apb1rstr) [
+        C1: (1, ccr1, ccmr1_output, 0x0001),
+    ],
+    TIM16: (tim16, tim16en, tim16rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output, 0x0001),
+    ],
+    TIM17: (tim17, tim17en, tim17rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output, 0x0001),
+    ],
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl<PINS, BKIN> Pwm<TIM1, PINS, BKIN> {
+    /// Sets the dead time inserted between an output and its complementary output switching on
+    ///
+    /// The dead time is rounded up to the next possible value, fails with
+    /// `Error::DeadTimeTooLong` if it's longer than 1008 timer clock cycles.
+    pub fn set_dead_time(&mut self, ns: u32) -> Result<(), Error> {
+        let ticks = (u64::from(ns) * u64::from(self.clocks.timclk().0)).div_ceil(1_000_000_000);
+
+        let dtg = match ticks {
+            0..=127 => ticks,
+            128..=254 => 0b1000_0000 | (ticks.div_ceil(2) - 64),
+            255..=504 => 0b1100_0000 | (ticks.div_ceil(8) - 32),
+            505..=1008 => 0b1110_0000 | (ticks.div_ceil(16) - 32),
+            _ => return Err(Error::DeadTimeTooLong),
+        };
+
+        self.tim.bdtr.modify(|_, w| unsafe { w.dtg().bits(dtg as u8) });
+        Ok(())
+    }
+
+    /// Returns whether a break condition occurred since the last `clear_break`
+    pub fn is_break_active(&self) -> bool {
+        self.tim.sr.read().bif().bit_is_set()
+    }
+
+    /// Clears the break flag
+    pub fn clear_break(&mut self) {
+        /* The flags are cleared by writing 0, writing 1 has no effect */
+        self.tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0080) });
+    }
+
+    /// Enables all outputs (MOE), e.g. after a break
+    pub fn enable_outputs(&mut self) {
+        self.tim.bdtr.modify(|_, w| w.moe().set_bit());
+    }
+
+    /// Disables all outputs (MOE)
+    pub fn disable_outputs(&mut self) {
+        self.tim.bdtr.modify(|_, w| w.moe().clear_bit());
+    }
+
+    /// Write protects parts of the configuration until the next reset
+    pub fn lock(&mut self, level: LockLevel) {
+        let bits = match level {
+            LockLevel::Level1 => 0b01,
+            LockLevel::Level2 => 0b10,
+            LockLevel::Level3 => 0b11,
+        };
+        self.tim.bdtr.modify(|_, w| unsafe { w.lock().bits(bits) });
+    }
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl<PINS> Pwm<TIM1, PINS> {
+    /// Enables the break input on `pin`, which disables all outputs while it's active
+    ///
+    /// The pin is kept until `disable_break`. With `automatic_output` the outputs are enabled
+    /// again at the next update event after the break condition went away, otherwise they stay
+    /// disabled until `enable_outputs` is called.
+    pub fn enable_break<P>(
+        self,
+        pin: P,
+        polarity: BreakPolarity,
+        automatic_output: bool,
+    ) -> Pwm<TIM1, PINS, P>
+    where
+        P: BkinPin<TIM1>,
+    {
+        self.tim.bdtr.modify(|_, w| {
+            w.bkp()
+             .bit(polarity == BreakPolarity::ActiveHigh)
+             .aoe()
+             .bit(automatic_output)
+             .bke()
+             .set_bit()
+        });
+
+        Pwm {
+            tim: self.tim,
+            pins: self.pins,
+            bkin: pin,
+            ccne: self.ccne,
+            clocks: self.clocks,
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl<PINS, P> Pwm<TIM1, PINS, P>
+where
+    P: BkinPin<TIM1>,
+{
+    /// Disables the break input and releases its pin
+    pub fn disable_break(self) -> (Pwm<TIM1, PINS>, P) {
+        self.tim.bdtr.modify(|_, w| w.bke().clear_bit());
+
+        let pwm = Pwm {
+            tim: self.tim,
+            pins: self.pins,
+            bkin: (),
+            ccne: self.ccne,
+            clocks: self.clocks,
+        };
+        (pwm, self.bkin)
+    }
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+pwm! {
+    TIM15: (tim15, tim15en, tim15rst, apb2enr, apb2rstr) [
+        C1: (1, ccr1, ccmr1_output, 0x0001),
+        C2: (2, ccr2, ccmr1_output, 0x0010),
+    ],
+}
+
+macro_rules! one_pulse {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl<PIN> OnePulse<$TIM, PIN> {
+                /// Configures the timer to output single pulses on the channel 1 `pin`
+                ///
+                /// No pulse is generated until one was set with `set_pulse` and then triggered.
+                pub fn $tim(tim: $TIM, pin: PIN, clocks: Clocks) -> Self
+                where
+                    PIN: PinC1<$TIM>,
+                {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    /* PWM mode 2 with preloaded compare value, the output is active from the
+                     * compare value to the end of the period */
+                    tim.ccmr1_output.write(|w| unsafe { w.bits(0b0111_1000) });
+                    tim.ccer.write(|w| unsafe { w.bits(0x0001) });
+
+                    tim.enable_main_output();
+
+                    /* Stop at the end of the period, only counter overflows flag an update */
+                    tim.cr1.modify(|_, w| w.opm().set_bit().urs().set_bit());
+
+                    OnePulse { tim, pin, clocks }
+                }
+
+                /// Sets the `delay` from the trigger to the start of the pulse and the pulse
+                /// `width`
+                ///
+                /// The delay is at least one timer tick. Fails if the pulse doesn't fit into the
+                /// counter range or would be shorter than one tick.
+                pub fn set_pulse<D, W>(&mut self, delay: D, width: W) -> Result<(), TimerError>
+                where
+                    D: Into<Timeout>,
+                    W: Into<Timeout>,
+                {
+                    let clk = self.clocks.timclk();
+                    let delay = delay.into().ticks(clk).ok_or(TimerError::InvalidTimeout)?;
+                    let width = width.into().ticks(clk).ok_or(TimerError::InvalidTimeout)?;
+
+                    let (psc, arr) = timers::psc_arr(delay + width, 0xFFFF)
+                        .ok_or(TimerError::InvalidTimeout)?;
+                    let ccr = cmp::max(delay / (u64::from(psc) + 1), 1);
+                    if width == 0 || ccr > u64::from(arr) {
+                        return Err(TimerError::InvalidTimeout);
+                    }
+
+                    self.tim.psc.write(|w| unsafe { w.bits(u32(psc)) });
+                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
+                    self.tim.ccr1.write(|w| unsafe { w.bits(ccr as u32) });
+
+                    /* Load the new values right away */
+                    self.tim.egr.write(|w| w.ug().set_bit());
+
+                    Ok(())
+                }
+
+                /// Starts a single pulse
+                pub fn trigger(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                }
+
+                /// Waits until the pulse is over
+                pub fn wait(&mut self) -> nb::Result<(), Void> {
+                    if self.tim.cr1.read().cen().bit_is_set() {
+                        Err(nb::Error::WouldBlock)
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                /// Releases the TIM peripheral and pin
+                pub fn release(self) -> ($TIM, PIN) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pin)
+                }
+            }
+        )+
+    };
+}
+
+// TIM14 has no one-pulse mode
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+one_pulse! {
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr),
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr),
+    TIM16: (tim16, tim16en, tim16rst, apb2enr, apb2rstr),
+    TIM17: (tim17, tim17en, tim17rst, apb2enr, apb2rstr),
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+one_pulse! {
+    TIM15: (tim15, tim15en, tim15rst, apb2enr, apb2rstr),
+}
@@ -0,0 +1,113 @@
+use stm32::{TIM1, TIM3};
+
+use hal::{self, Direction};
+
+use pwm::{PinC1, PinC2};
+
+/// Quadrature encoder interface
+pub struct Qei<TIM, PINS> {
+    tim: TIM,
+    pins: PINS,
+    last_count: u16,
+    position: i32,
+}
+
+/// Encoder edges the counter counts on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Edges of channel 1, depending on the level of channel 2
+    Ti1 = 0b001,
+    /// Edges of channel 2, depending on the level of channel 1
+    Ti2 = 0b010,
+    /// Edges of both channels, four counts per encoder cycle
+    Both = 0b011,
+}
+
+macro_rules! qei {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl<PC1, PC2> Qei<$TIM, (PC1, PC2)> {
+                /// Configures the timer as quadrature encoder interface on the channel 1 and 2
+                /// `pins`, counting in `mode`
+                pub fn $tim(tim: $TIM, pins: (PC1, PC2), mode: Mode) -> Self
+                where
+                    PC1: PinC1<$TIM>,
+                    PC2: PinC2<$TIM>,
+                {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    /* Channels 1 and 2 capture their own, non-inverted inputs */
+                    tim.ccmr1_output.write(|w| unsafe { w.bits(0b01 | 0b01 << 8) });
+                    tim.ccer.write(|w| unsafe { w.bits(0x0001 | 0x0010) });
+
+                    tim.smcr.write(|w| unsafe { w.sms().bits(mode as u8) });
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    Qei {
+                        tim,
+                        pins,
+                        last_count: 0,
+                        position: 0,
+                    }
+                }
+
+                /// Returns the position accumulated since the start or the last `reset`
+                ///
+                /// Counter wraparounds are tracked, as long as this is called at least once per
+                /// 32767 counts.
+                pub fn position(&mut self) -> i32 {
+                    let count = self.tim.cnt.read().bits() as u16;
+                    let delta = count.wrapping_sub(self.last_count) as i16;
+
+                    self.last_count = count;
+                    self.position = self.position.wrapping_add(i32::from(delta));
+                    self.position
+                }
+
+                /// Sets the counter and the position to 0
+                pub fn reset(&mut self) {
+                    self.tim.cnt.write(|w| unsafe { w.bits(0) });
+                    self.last_count = 0;
+                    self.position = 0;
+                }
+
+                /// Stops the timer and releases the TIM peripheral and pins
+                pub fn release(self) -> ($TIM, (PC1, PC2)) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    (self.tim, self.pins)
+                }
+            }
+
+            impl<PINS> hal::Qei for Qei<$TIM, PINS> {
+                type Count = u16;
+
+                fn count(&self) -> u16 {
+                    self.tim.cnt.read().bits() as u16
+                }
+
+                fn direction(&self) -> Direction {
+                    if self.tim.cr1.read().dir().bit_is_set() {
+                        Direction::Downcounting
+                    } else {
+                        Direction::Upcounting
+                    }
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+qei! {
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr),
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr),
+}
@@ -18,6 +18,7 @@ impl RccExt for RCC {
                 hclk: None,
                 pclk: None,
                 sysclk: None,
+                i2c1src: I2cClockSource::Hsi,
             },
         }
     }
@@ -30,10 +31,20 @@ pub struct Rcc {
 
 const HSI: u32 = 8_000_000; // Hz
 
+/// Clock source of the I2C1 peripheral
+#[derive(Clone, Copy, PartialEq)]
+pub enum I2cClockSource {
+    /// The 8 MHz internal oscillator
+    Hsi,
+    /// The system clock
+    Sysclk,
+}
+
 pub struct CFGR {
     hclk: Option<u32>,
     pclk: Option<u32>,
     sysclk: Option<u32>,
+    i2c1src: I2cClockSource,
 }
 
 impl CFGR {
@@ -61,6 +72,12 @@ impl CFGR {
         self
     }
 
+    /// Selects the clock source of the I2C1 peripheral, defaults to HSI
+    pub fn i2c1_clock_source(mut self, source: I2cClockSource) -> Self {
+        self.i2c1src = source;
+        self
+    }
+
     pub fn freeze(self) -> Clocks {
         let pllmul = (4 * self.sysclk.unwrap_or(HSI) + HSI) / HSI / 2;
         let pllmul = cmp::min(cmp::max(pllmul, 2), 16);
@@ -138,10 +155,19 @@ impl CFGR {
                 .write(|w| unsafe { w.ppre().bits(ppre_bits).hpre().bits(hpre_bits).sw().bits(0) });
         }
 
+        rcc.cfgr3
+            .modify(|_, w| w.i2c1sw().bit(self.i2c1src == I2cClockSource::Sysclk));
+        let i2c1clk = match self.i2c1src {
+            I2cClockSource::Hsi => HSI,
+            I2cClockSource::Sysclk => sysclk,
+        };
+
         Clocks {
             hclk: Hertz(hclk),
             pclk: Hertz(pclk),
+            ppre,
             sysclk: Hertz(sysclk),
+            i2c1clk: Hertz(i2c1clk),
         }
     }
 }
@@ -153,7 +179,9 @@ impl CFGR {
 pub struct Clocks {
     hclk: Hertz,
     pclk: Hertz,
+    ppre: u8,
     sysclk: Hertz,
+    i2c1clk: Hertz,
 }
 
 impl Clocks {
@@ -167,8 +195,22 @@ impl Clocks {
         self.pclk
     }
 
+    /// Returns the frequency of the timer clock, which is doubled if the APB is prescaled
+    pub fn timclk(&self) -> Hertz {
+        if self.ppre == 1 {
+            self.pclk
+        } else {
+            Hertz(self.pclk.0 * 2)
+        }
+    }
+
     /// Returns the system (core) frequency
     pub fn sysclk(&self) -> Hertz {
         self.sysclk
     }
+
+    /// Returns the frequency of the I2C1 clock
+    pub fn i2c1clk(&self) -> Hertz {
+        self.i2c1clk
+    }
 }
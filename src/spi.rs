@@ -1,11 +1,13 @@
+use core::cmp;
 use core::ptr;
 
 use nb;
 
+use hal::spi::FullDuplex;
 pub use hal::spi::{Mode, Phase, Polarity};
 use rcc::Clocks;
 
-use stm32::{RCC, SPI1, SPI2};
+use stm32::{spi1, RCC, SPI1, SPI2};
 
 use gpio::*;
 use gpio::{Alternate, AF0};
@@ -20,6 +22,10 @@ pub enum Error {
     ModeFault,
     /// CRC error
     Crc,
+    /// Underrun occurred, the master clocked out a frame before the slave provided data for it
+    ///
+    /// Only reported to a slave, the received frame is returned by the next `read`.
+    Underrun,
     #[doc(hidden)]
     _Extensible,
 }
@@ -28,10 +34,39 @@ pub enum Error {
 pub struct Spi<SPI, PINS> {
     spi: SPI,
     pins: PINS,
+    // Underrun accounting, only done by a slave
+    slave: bool,
+    // Number of frames written to the TX FIFO that have not been matched by a received frame yet
+    queued: u32,
+    // An underrun was detected and is reported by the next `read`
+    underrun: bool,
+    // Frame received while reporting an underrun, returned by the following `read`
+    held: Option<u8>,
 }
 
 pub trait Pins<Spi> {}
 
+/// Pins including the hardware NSS pin, used as slave select input or output
+pub trait NssPins<Spi> {}
+
+/// 3-wire half-duplex SPI abstraction, sharing a single data line for both directions
+pub struct BidiSpi<SPI, PINS> {
+    spi: SPI,
+    pins: PINS,
+}
+
+/// Direction of the bidirectional data line
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// The data line is driven by us
+    Output,
+    /// The data line is driven by the slave, the clock runs as long as the SPI is enabled
+    Input,
+}
+
+/// Pins usable in 3-wire half-duplex mode, MOSI is used as the bidirectional data line
+pub trait BidiPins<Spi> {}
+
 // TODO Add all possible pin bindings
 #[cfg(any(
     feature = "stm32f030f4",
@@ -66,8 +101,127 @@ impl Pins<SPI1>
 {
 }
 
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl NssPins<SPI1>
+    for (
+        gpioa::PA5<Alternate<AF0>>,
+        gpioa::PA6<Alternate<AF0>>,
+        gpioa::PA7<Alternate<AF0>>,
+        gpioa::PA4<Alternate<AF0>>,
+    )
+{
+}
+
+#[cfg(any(
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl NssPins<SPI1>
+    for (
+        gpioa::PA5<Alternate<AF0>>,
+        gpioa::PA6<Alternate<AF0>>,
+        gpioa::PA7<Alternate<AF0>>,
+        gpioa::PA15<Alternate<AF0>>,
+    )
+{
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl NssPins<SPI2>
+    for (
+        gpiob::PB13<Alternate<AF0>>,
+        gpiob::PB14<Alternate<AF0>>,
+        gpiob::PB15<Alternate<AF0>>,
+        gpiob::PB12<Alternate<AF0>>,
+    )
+{
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl BidiPins<SPI1> for (gpioa::PA5<Alternate<AF0>>, gpioa::PA7<Alternate<AF0>>) {}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+impl BidiPins<SPI2> for (gpiob::PB13<Alternate<AF0>>, gpiob::PB15<Alternate<AF0>>) {}
+
+fn baud_rate_bits(clocks: Clocks, speed: Hertz) -> u8 {
+    match clocks.pclk().0 / speed.0 {
+        0 => unreachable!(),
+        1...2 => 0b000,
+        3...5 => 0b001,
+        6...11 => 0b010,
+        12...23 => 0b011,
+        24...47 => 0b100,
+        48...95 => 0b101,
+        96...191 => 0b110,
+        _ => 0b111,
+    }
+}
+
+/// Returns the CPOL bit for `mode`, the clock idles high if set
+fn cpol(mode: &Mode) -> bool {
+    mode.polarity == Polarity::IdleHigh
+}
+
+/// Returns the CPHA bit for `mode`, data is captured on the second clock edge if set
+fn cpha(mode: &Mode) -> bool {
+    mode.phase == Phase::CaptureOnSecondTransition
+}
+
+/// Drains the RX FIFO, the following status register read also clears a pending overrun
+///
+/// Returns the number of discarded frames.
+fn drain_rx(spi: &spi1::RegisterBlock) -> u32 {
+    let mut frames = 0;
+    while spi.sr.read().frlvl().bits() != 0 {
+        // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows reading a half-word)
+        unsafe { ptr::read_volatile(&spi.dr as *const _ as *const u8) };
+        frames += 1;
+    }
+    spi.sr.read();
+    frames
+}
+
+/// Returns the most 8 bit frames a FIFO with the FTLVL/FRLVL value `level` can hold, a full
+/// FIFO reads the same with 3 or 4 frames
+fn fifo_frames(level: u8) -> u32 {
+    match level {
+        0b11 => 4,
+        level => u32::from(level),
+    }
+}
+
 macro_rules! spi {
-    ($($SPI:ident: ($spi:ident, $spiXen:ident, $spiXrst:ident, $apbenr:ident, $apbrstr:ident ),)+) => {
+    ($($SPI:ident: ($spi:ident, $spi_nss:ident, $spi_ti:ident, $spi_slave:ident, $spiXen:ident, $spiXrst:ident, $apbenr:ident, $apbrstr:ident ),)+) => {
         $(
             impl<PINS> Spi<$SPI, PINS> {
                 pub fn $spi<F>(spi: $SPI, pins: PINS, mode: Mode, speed: F, clocks: Clocks) -> Self
@@ -75,18 +229,7 @@ macro_rules! spi {
                     PINS: Pins<$SPI>,
                     F: Into<Hertz>,
                 {
-                    // NOTE(unsafe) This executes only during initialisation
-                    let rcc = unsafe { &(*RCC::ptr()) };
-
-                    /* Enable clock for SPI */
-                    rcc.$apbenr.modify(|_, w| w.$spiXen().set_bit());
-
-                    /* Reset SPI */
-                    rcc.$apbrstr.modify(|_, w| w.$spiXrst().set_bit());
-                    rcc.$apbrstr.modify(|_, w| w.$spiXrst().clear_bit());
-
-                    /* Make sure the SPI unit is disabled so we can configure it */
-                    spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    Self::reset(&spi);
 
                     // FRXTH: 8-bit threshold on RX FIFO
                     // DS: 8-bit data size
@@ -97,17 +240,7 @@ macro_rules! spi {
                     spi.cr2
                        .write(|w| unsafe { w.frxth().set_bit().ds().bits(0b0111).ssoe().clear_bit() });
 
-                    let br = match clocks.pclk().0 / speed.into().0 {
-                        0 => unreachable!(),
-                        1...2 => 0b000,
-                        3...5 => 0b001,
-                        6...11 => 0b010,
-                        12...23 => 0b011,
-                        24...47 => 0b100,
-                        48...95 => 0b101,
-                        96...191 => 0b110,
-                        _ => 0b111,
-                    };
+                    let br = baud_rate_bits(clocks, speed.into());
 
                     // mstr: master configuration
                     // lsbfirst: MSB first
@@ -118,9 +251,9 @@ macro_rules! spi {
                     // spe: enable the SPI bus
                     spi.cr1.write(|w| unsafe {
                         w.cpha()
-                         .bit(mode.phase == Phase::CaptureOnSecondTransition)
+                         .bit(cpha(&mode))
                          .cpol()
-                         .bit(mode.polarity == Polarity::IdleHigh)
+                         .bit(cpol(&mode))
                          .mstr()
                          .set_bit()
                          .br()
@@ -139,30 +272,370 @@ macro_rules! spi {
                          .set_bit()
                     });
 
-                    Spi { spi, pins }
+                    Spi {
+                        spi,
+                        pins,
+                        slave: false,
+                        queued: 0,
+                        underrun: false,
+                        held: None,
+                    }
+                }
+
+                /// Configures the SPI peripheral as a master driving the hardware NSS output
+                ///
+                /// NSS is pulsed high between two consecutive frames with the
+                /// `CaptureOnFirstTransition` phase, with `CaptureOnSecondTransition` it stays low
+                /// while the peripheral is enabled.
+                pub fn $spi_nss<F>(spi: $SPI, pins: PINS, mode: Mode, speed: F, clocks: Clocks) -> Self
+                where
+                    PINS: NssPins<$SPI>,
+                    F: Into<Hertz>,
+                {
+                    // The NSS pulse is only generated if data is captured on the first edge
+                    let nssp = mode.phase == Phase::CaptureOnFirstTransition;
+
+                    Self::reset(&spi);
+
+                    // FRXTH: 8-bit threshold on RX FIFO
+                    // DS: 8-bit data size
+                    // SSOE: set to drive NSS as an output
+                    // NSSP: pulse NSS between frames if possible
+                    spi.cr2.write(|w| unsafe {
+                        w.frxth()
+                         .set_bit()
+                         .ds()
+                         .bits(0b0111)
+                         .ssoe()
+                         .set_bit()
+                         .nssp()
+                         .bit(nssp)
+                    });
+
+                    let br = baud_rate_bits(clocks, speed.into());
+
+                    // mstr: master configuration
+                    // lsbfirst: MSB first
+                    // ssm: disable software slave management, NSS is driven by the hardware
+                    // bidimode: 2-line unidirectional
+                    // spe: enable the SPI bus
+                    spi.cr1.write(|w| unsafe {
+                        w.cpha()
+                         .bit(cpha(&mode))
+                         .cpol()
+                         .bit(cpol(&mode))
+                         .mstr()
+                         .set_bit()
+                         .br()
+                         .bits(br)
+                         .lsbfirst()
+                         .clear_bit()
+                         .ssm()
+                         .clear_bit()
+                         .rxonly()
+                         .clear_bit()
+                         .bidimode()
+                         .clear_bit()
+                         .spe()
+                         .set_bit()
+                    });
+
+                    Spi {
+                        spi,
+                        pins,
+                        slave: false,
+                        queued: 0,
+                        underrun: false,
+                        held: None,
+                    }
+                }
+
+                /// Configures the SPI peripheral as a master using the TI frame format
+                ///
+                /// Clock polarity, phase and the NSS pulse are fixed by the TI protocol.
+                pub fn $spi_ti<F>(spi: $SPI, pins: PINS, speed: F, clocks: Clocks) -> Self
+                where
+                    PINS: NssPins<$SPI>,
+                    F: Into<Hertz>,
+                {
+                    Self::reset(&spi);
+
+                    // FRXTH: 8-bit threshold on RX FIFO
+                    // DS: 8-bit data size
+                    // FRF: TI frame format
+                    spi.cr2
+                       .write(|w| unsafe { w.frxth().set_bit().ds().bits(0b0111).frf().set_bit() });
+
+                    let br = baud_rate_bits(clocks, speed.into());
+
+                    // mstr: master configuration
+                    // bidimode: 2-line unidirectional
+                    // spe: enable the SPI bus
+                    spi.cr1.write(|w| unsafe {
+                        w.mstr()
+                         .set_bit()
+                         .br()
+                         .bits(br)
+                         .rxonly()
+                         .clear_bit()
+                         .bidimode()
+                         .clear_bit()
+                         .spe()
+                         .set_bit()
+                    });
+
+                    Spi {
+                        spi,
+                        pins,
+                        slave: false,
+                        queued: 0,
+                        underrun: false,
+                        held: None,
+                    }
+                }
+
+                /// Configures the SPI peripheral as a slave selected by the hardware NSS input
+                ///
+                /// The master clocks out `preload` in the first frame of a transfer, every
+                /// following frame has to be queued with `send` before the master clocks it.
+                pub fn $spi_slave(spi: $SPI, pins: PINS, mode: Mode, preload: u8) -> Self
+                where
+                    PINS: NssPins<$SPI>,
+                {
+                    Self::reset(&spi);
+
+                    // FRXTH: 8-bit threshold on RX FIFO
+                    // DS: 8-bit data size
+                    // SSOE: cleared, NSS is an input in slave mode
+                    spi.cr2
+                       .write(|w| unsafe { w.frxth().set_bit().ds().bits(0b0111).ssoe().clear_bit() });
+
+                    // mstr: slave configuration
+                    // lsbfirst: MSB first
+                    // ssm: disable software slave management, NSS pin selects the slave
+                    // bidimode: 2-line unidirectional
+                    // spe: enable the SPI bus
+                    spi.cr1.write(|w| {
+                        w.cpha()
+                         .bit(cpha(&mode))
+                         .cpol()
+                         .bit(cpol(&mode))
+                         .mstr()
+                         .clear_bit()
+                         .lsbfirst()
+                         .clear_bit()
+                         .ssm()
+                         .clear_bit()
+                         .rxonly()
+                         .clear_bit()
+                         .bidimode()
+                         .clear_bit()
+                         .spe()
+                         .set_bit()
+                    });
+
+                    // NOTE(write_volatile) see note in `send`
+                    unsafe { ptr::write_volatile(&spi.dr as *const _ as *mut u8, preload) }
+
+                    Spi {
+                        spi,
+                        pins,
+                        slave: true,
+                        queued: 1,
+                        underrun: false,
+                        held: None,
+                    }
+                }
+
+                fn reset(spi: &$SPI) {
+                    // NOTE(unsafe) This executes only during initialisation
+                    let rcc = unsafe { &(*RCC::ptr()) };
+
+                    /* Enable clock for SPI */
+                    rcc.$apbenr.modify(|_, w| w.$spiXen().set_bit());
+
+                    /* Reset SPI */
+                    rcc.$apbrstr.modify(|_, w| w.$spiXrst().set_bit());
+                    rcc.$apbrstr.modify(|_, w| w.$spiXrst().clear_bit());
+
+                    /* Make sure the SPI unit is disabled so we can configure it */
+                    spi.cr1.modify(|_, w| w.spe().clear_bit());
                 }
 
                 pub fn release(self) -> ($SPI, PINS) {
                     (self.spi, self.pins)
                 }
+
+                /// Clears any overrun, mode fault or CRC error and puts the SPI back into a
+                /// working state
+                ///
+                /// All received frames that haven't been read yet are discarded. This is done
+                /// automatically whenever one of these errors is returned.
+                pub fn recover(&mut self) {
+                    let sr = self.spi.sr.read();
+
+                    if sr.modf().bit_is_set() {
+                        /* A mode fault disables the SPI and drops to slave mode, writing CR1 after
+                         * reading SR clears the flag. This can only happen to a master. */
+                        self.spi.cr1.modify(|_, w| w.mstr().set_bit().spe().set_bit());
+                    }
+
+                    if sr.crcerr().bit_is_set() {
+                        self.spi.sr.modify(|_, w| w.crcerr().clear_bit());
+                    }
+
+                    if self.spi.cr1.read().mstr().bit_is_set() {
+                        /* Let the master finish the frames already queued up */
+                        while self.spi.sr.read().ftlvl().bits() != 0 {}
+                        while self.spi.sr.read().bsy().bit_is_set() {}
+                        drain_rx(&self.spi);
+                    } else {
+                        /* The frames in the TX FIFO and the one already loaded into the shift
+                         * register of a slave are still sent to the master, frames lost to an
+                         * overrun consumed queued frames without being counted */
+                        let drained = drain_rx(&self.spi);
+                        let pending = fifo_frames(self.spi.sr.read().ftlvl().bits()) + 1;
+                        self.queued = cmp::min(self.queued.saturating_sub(drained), pending);
+                    }
+                    self.underrun = false;
+                    self.held = None;
+                }
+
+                /// Enables the hardware CRC calculation using an 8 bit `polynomial`
+                pub fn enable_crc(&mut self, polynomial: u8) {
+                    /* The CRC may only be configured while the SPI unit is disabled */
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+
+                    self.spi
+                        .crcpr
+                        .write(|w| unsafe { w.crcpoly().bits(u16::from(polynomial)) });
+
+                    // dff: 8 bit CRC length
+                    self.spi
+                        .cr1
+                        .modify(|_, w| w.dff().clear_bit().crcen().set_bit().spe().set_bit());
+                }
+
+                /// Disables the hardware CRC calculation
+                pub fn disable_crc(&mut self) {
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().clear_bit().spe().set_bit());
+                }
+
+                /// Transfers `words` followed by the CRC, the received CRC is checked by the
+                /// hardware
+                ///
+                /// A CRC mismatch is reported as `Error::Crc`. The CRC has to be enabled with
+                /// `enable_crc` beforehand.
+                pub fn transfer_with_crc<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Error> {
+                    if words.is_empty() {
+                        return Ok(words);
+                    }
+
+                    self.reset_crc();
+
+                    let last = words.len() - 1;
+                    for (i, word) in words.iter_mut().enumerate() {
+                        block!(self.send(*word))?;
+                        if i == last {
+                            /* Send the CRC right after the last data frame */
+                            self.spi.cr1.modify(|_, w| w.crcnext().set_bit());
+                        }
+                        *word = block!(self.read())?;
+                    }
+
+                    self.check_crc()?;
+                    Ok(words)
+                }
+
+                /// Writes `words` followed by the CRC, discarding the received data
+                ///
+                /// The CRC has to be enabled with `enable_crc` beforehand.
+                pub fn write_with_crc(&mut self, words: &[u8]) -> Result<(), Error> {
+                    if words.is_empty() {
+                        return Ok(());
+                    }
+
+                    self.reset_crc();
+
+                    let last = words.len() - 1;
+                    for (i, word) in words.iter().enumerate() {
+                        block!(self.send(*word))?;
+                        if i == last {
+                            /* Send the CRC right after the last data frame */
+                            self.spi.cr1.modify(|_, w| w.crcnext().set_bit());
+                        }
+                        block!(self.read())?;
+                    }
+
+                    self.check_crc()
+                }
+
+                fn reset_crc(&mut self) {
+                    /* The CRC registers are cleared by toggling CRCEN while the SPI unit is disabled */
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().clear_bit());
+                    self.spi.cr1.modify(|_, w| w.crcen().set_bit().spe().set_bit());
+                }
+
+                fn check_crc(&mut self) -> Result<(), Error> {
+                    /* Wait for the CRC frame of the other side */
+                    while self.spi.sr.read().rxne().bit_is_clear() {}
+
+                    // NOTE(read_volatile) see note in `read`, the CRC frame itself is discarded
+                    unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) };
+
+                    if self.spi.sr.read().crcerr().bit_is_set() {
+                        self.spi.sr.modify(|_, w| w.crcerr().clear_bit());
+                        Err(Error::Crc)
+                    } else {
+                        Ok(())
+                    }
+                }
             }
 
             impl<PINS> ::hal::spi::FullDuplex<u8> for Spi<$SPI, PINS> {
                 type Error = Error;
 
                 fn read(&mut self) -> nb::Result<u8, Error> {
+                    if let Some(byte) = self.held.take() {
+                        return Ok(byte);
+                    }
+
                     let sr = self.spi.sr.read();
 
                     Err(if sr.ovr().bit_is_set() {
+                        self.recover();
                         nb::Error::Other(Error::Overrun)
                     } else if sr.modf().bit_is_set() {
+                        self.recover();
                         nb::Error::Other(Error::ModeFault)
                     } else if sr.crcerr().bit_is_set() {
+                        self.recover();
                         nb::Error::Other(Error::Crc)
                     } else if sr.rxne().bit_is_set() {
                         // NOTE(read_volatile) read only 1 byte (the svd2rust API only allows
                         // reading a half-word)
-                        return Ok(unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) });
+                        let byte = unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) };
+
+                        if self.slave {
+                            // Every received frame shifted out one queued frame, if there was
+                            // none the slave sent stale data to the master
+                            if self.queued == 0 {
+                                self.underrun = true;
+                            } else {
+                                self.queued -= 1;
+                            }
+                        }
+
+                        if self.underrun {
+                            // Report the underrun first, the frame itself is still valid
+                            self.underrun = false;
+                            self.held = Some(byte);
+                            nb::Error::Other(Error::Underrun)
+                        } else {
+                            return Ok(byte);
+                        }
                     } else {
                         nb::Error::WouldBlock
                     })
@@ -172,14 +645,31 @@ macro_rules! spi {
                     let sr = self.spi.sr.read();
 
                     Err(if sr.ovr().bit_is_set() {
+                        self.recover();
                         nb::Error::Other(Error::Overrun)
                     } else if sr.modf().bit_is_set() {
+                        self.recover();
                         nb::Error::Other(Error::ModeFault)
                     } else if sr.crcerr().bit_is_set() {
+                        self.recover();
                         nb::Error::Other(Error::Crc)
                     } else if sr.txe().bit_is_set() {
+                        if self.slave {
+                            // Frames the master already clocked in without a queued frame got
+                            // stale data, this one only goes out with a later frame. A full RX
+                            // FIFO may hold more than the 3 frames its level reads as.
+                            let received = u32::from(sr.frlvl().bits());
+                            if self.queued < received {
+                                self.underrun = true;
+                                self.queued = received;
+                            }
+                        }
+
                         // NOTE(write_volatile) see note above
                         unsafe { ptr::write_volatile(&self.spi.dr as *const _ as *mut u8, byte) }
+                        if self.slave {
+                            self.queued += 1;
+                        }
                         return Ok(());
                     } else {
                         nb::Error::WouldBlock
@@ -189,6 +679,184 @@ macro_rules! spi {
 
             impl<PINS> ::hal::blocking::spi::transfer::Default<u8> for Spi<$SPI, PINS> {}
             impl<PINS> ::hal::blocking::spi::write::Default<u8> for Spi<$SPI, PINS> {}
+
+            impl<PINS> BidiSpi<$SPI, PINS> {
+                /// Configures the SPI peripheral as a 3-wire half-duplex master
+                ///
+                /// The data line starts out in the `Output` direction.
+                pub fn $spi<F>(spi: $SPI, pins: PINS, mode: Mode, speed: F, clocks: Clocks) -> Self
+                where
+                    PINS: BidiPins<$SPI>,
+                    F: Into<Hertz>,
+                {
+                    Spi::<$SPI, PINS>::reset(&spi);
+
+                    // FRXTH: 8-bit threshold on RX FIFO
+                    // DS: 8-bit data size
+                    // SSOE: cleared to disable SS output
+                    spi.cr2
+                       .write(|w| unsafe { w.frxth().set_bit().ds().bits(0b0111).ssoe().clear_bit() });
+
+                    let br = baud_rate_bits(clocks, speed.into());
+
+                    // mstr: master configuration
+                    // lsbfirst: MSB first
+                    // ssm: enable software slave management (NSS pin free for other uses)
+                    // ssi: set nss high = master mode
+                    // bidimode: 1-line bidirectional
+                    // bidioe: output enabled
+                    // spe: enable the SPI bus
+                    spi.cr1.write(|w| unsafe {
+                        w.cpha()
+                         .bit(cpha(&mode))
+                         .cpol()
+                         .bit(cpol(&mode))
+                         .mstr()
+                         .set_bit()
+                         .br()
+                         .bits(br)
+                         .lsbfirst()
+                         .clear_bit()
+                         .ssm()
+                         .set_bit()
+                         .ssi()
+                         .set_bit()
+                         .rxonly()
+                         .clear_bit()
+                         .bidimode()
+                         .set_bit()
+                         .bidioe()
+                         .set_bit()
+                         .spe()
+                         .set_bit()
+                    });
+
+                    BidiSpi { spi, pins }
+                }
+
+                pub fn release(self) -> ($SPI, PINS) {
+                    (self.spi, self.pins)
+                }
+
+                /// Switches the direction of the data line
+                ///
+                /// Switching to `Input` immediately starts clocking in data until the direction is
+                /// switched back, use `read` to receive a fixed number of frames instead.
+                pub fn set_direction(&mut self, direction: Direction) {
+                    /* Wait for an ongoing transmission to finish */
+                    while self.spi.sr.read().ftlvl().bits() != 0 {}
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    self.spi.cr1.modify(|_, w| {
+                        w.bidioe()
+                         .bit(direction == Direction::Output)
+                         .spe()
+                         .set_bit()
+                    });
+                }
+
+                /// Reads `words` from the slave
+                ///
+                /// The data line is switched back to the `Output` direction afterwards, which stops
+                /// the clock.
+                pub fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+                    let (last, words) = match words.split_last_mut() {
+                        Some(split) => split,
+                        None => return Ok(()),
+                    };
+
+                    /* Switching to input starts the clock */
+                    self.set_direction(Direction::Input);
+
+                    for word in words {
+                        *word = self.recv_byte()?;
+                    }
+
+                    /* Stop the clock while the last frame is being received */
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    *last = self.recv_byte()?;
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+
+                    /* Drop any frame clocked in before the SPI was disabled */
+                    while self.spi.sr.read().frlvl().bits() != 0 {
+                        // NOTE(read_volatile) see note in `Spi::read`
+                        unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) };
+                    }
+
+                    self.spi.cr1.modify(|_, w| w.bidioe().set_bit().spe().set_bit());
+
+                    Ok(())
+                }
+
+                /// Clears any overrun or mode fault and puts the SPI back into a working state
+                ///
+                /// The data line is switched back to the `Output` direction and all received
+                /// frames that haven't been read yet are discarded. This is done automatically
+                /// whenever one of these errors is returned.
+                pub fn recover(&mut self) {
+                    let sr = self.spi.sr.read();
+
+                    /* Stop the clock of an ongoing read, writing CR1 after reading SR also clears
+                     * a mode fault */
+                    self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                    if sr.modf().bit_is_clear() {
+                        while self.spi.sr.read().bsy().bit_is_set() {}
+                    }
+
+                    drain_rx(&self.spi);
+
+                    self.spi
+                        .cr1
+                        .modify(|_, w| w.mstr().set_bit().bidioe().set_bit().spe().set_bit());
+                }
+
+                fn recv_byte(&mut self) -> Result<u8, Error> {
+                    loop {
+                        let sr = self.spi.sr.read();
+                        if sr.ovr().bit_is_set() {
+                            self.recover();
+                            return Err(Error::Overrun);
+                        } else if sr.modf().bit_is_set() {
+                            self.recover();
+                            return Err(Error::ModeFault);
+                        } else if sr.rxne().bit_is_set() {
+                            // NOTE(read_volatile) see note in `Spi::read`
+                            return Ok(unsafe { ptr::read_volatile(&self.spi.dr as *const _ as *const u8) });
+                        }
+                    }
+                }
+            }
+
+            impl<PINS> ::hal::blocking::spi::Write<u8> for BidiSpi<$SPI, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+                    if self.spi.cr1.read().bidioe().bit_is_clear() {
+                        self.set_direction(Direction::Output);
+                    }
+
+                    for word in words {
+                        while {
+                            let sr = self.spi.sr.read();
+                            if sr.modf().bit_is_set() {
+                                self.recover();
+                                return Err(Error::ModeFault);
+                            }
+                            sr.txe().bit_is_clear()
+                        } {}
+
+                        // NOTE(write_volatile) see note in `Spi::send`
+                        unsafe { ptr::write_volatile(&self.spi.dr as *const _ as *mut u8, *word) }
+                    }
+
+                    /* Wait until everything has been shifted out */
+                    while self.spi.sr.read().ftlvl().bits() != 0 {}
+                    while self.spi.sr.read().bsy().bit_is_set() {}
+
+                    Ok(())
+                }
+            }
         )+
     }
 }
@@ -203,7 +871,7 @@ macro_rules! spi {
     feature = "stm32f030rc"
 ))]
 spi! {
-    SPI1: (spi1, spi1en, spi1rst, apb2enr, apb2rstr),
+    SPI1: (spi1, spi1_nss, spi1_ti, spi1_slave, spi1en, spi1rst, apb2enr, apb2rstr),
 }
 
 #[cfg(any(
@@ -213,5 +881,19 @@ spi! {
     feature = "stm32f030rc"
 ))]
 spi! {
-    SPI2: (spi2, spi2en, spi2rst, apb1enr, apb1rstr),
+    SPI2: (spi2, spi2_nss, spi2_ti, spi2_slave, spi2en, spi2rst, apb1enr, apb1rstr),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cpha, cpol};
+    use hal::spi::{MODE_0, MODE_1, MODE_2, MODE_3};
+
+    #[test]
+    fn clock_mode() {
+        assert_eq!((cpol(&MODE_0), cpha(&MODE_0)), (false, false));
+        assert_eq!((cpol(&MODE_1), cpha(&MODE_1)), (false, true));
+        assert_eq!((cpol(&MODE_2), cpha(&MODE_2)), (true, false));
+        assert_eq!((cpol(&MODE_3), cpha(&MODE_3)), (true, true));
+    }
 }
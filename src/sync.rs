@@ -0,0 +1,170 @@
+//! Master/slave synchronization of timers
+//!
+//! A master timer drives its trigger output (TRGO) from one of its events, which a slave timer
+//! selects as trigger input to reset, gate, start or clock its counter. Only the internal
+//! trigger connections the F030 has are available, see `TriggerSource`.
+//!
+//! To run several PWM timers in lockstep, `stop` the slaves, put them into `SlaveMode::Trigger`
+//! of a master in `MasterMode::Enable` and then `stop` and `start` the master.
+
+use stm32::{TIM1, TIM14, TIM15, TIM16, TIM17, TIM3};
+
+use pwm::Pwm;
+use timers::{MonoTimer, Timer};
+
+/// Event driving the trigger output of a master timer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MasterMode {
+    /// Counter reset by `EGR.UG` or a slave mode reset
+    Reset = 0b000,
+    /// Counter enable
+    Enable = 0b001,
+    /// Update event, e.g. counter overflow
+    Update = 0b010,
+    /// Pulse on every capture or compare match of channel 1
+    ComparePulse = 0b011,
+    /// Output compare reference of channel 1
+    Compare1 = 0b100,
+    /// Output compare reference of channel 2
+    Compare2 = 0b101,
+    /// Output compare reference of channel 3, not on TIM15
+    Compare3 = 0b110,
+    /// Output compare reference of channel 4, not on TIM15
+    Compare4 = 0b111,
+}
+
+/// Reaction of a slave timer to its trigger input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlaveMode {
+    /// Trigger input ignored, the counter runs on the internal clock
+    Disabled = 0b000,
+    /// Rising edges reset the counter
+    Reset = 0b100,
+    /// The counter runs while the trigger is high
+    Gated = 0b101,
+    /// Rising edges start the counter
+    Trigger = 0b110,
+    /// Rising edges clock the counter
+    ExternalClock = 0b111,
+}
+
+/// Timer whose trigger output is connected to an internal trigger input of `SLAVE`
+///
+/// TIM14, TIM16 and TIM17 have no master mode, their trigger output is the output compare
+/// reference of channel 1.
+pub trait TriggerSource<SLAVE> {
+    #[doc(hidden)]
+    const ITR: u8;
+}
+
+macro_rules! trigger_sources {
+    ($($SLAVE:ident: [$($MASTER:ident: $itr:expr),+],)+) => {
+        $(
+            $(
+                impl TriggerSource<$SLAVE> for $MASTER {
+                    const ITR: u8 = $itr;
+                }
+            )+
+        )+
+    };
+}
+
+macro_rules! master_mode {
+    ($max_mode:expr) => {
+        /// Selects the event driving the trigger output
+        // NOTE(allow) `w.mms().bits()` is safe for TIM3 but not for TIM1 and TIM15
+        #[allow(unused_unsafe)]
+        pub fn set_master_mode(&mut self, mode: MasterMode) {
+            assert!(mode as u8 <= $max_mode as u8, "timer doesn't have this master mode");
+            self.tim.cr2.modify(|_, w| unsafe { w.mms().bits(mode as u8) });
+        }
+    };
+}
+
+macro_rules! slave_mode {
+    ($TIM:ident) => {
+        /// Makes the counter react to the trigger output of the timer `MASTER` in `mode`
+        ///
+        /// A counter started by `SlaveMode::Trigger` has to be stopped beforehand.
+        pub fn set_slave_mode<MASTER>(&mut self, mode: SlaveMode)
+        where
+            MASTER: TriggerSource<$TIM>,
+        {
+            /* The trigger may only be changed while the slave mode is disabled */
+            self.tim.smcr.modify(|_, w| unsafe { w.sms().bits(0b000) });
+            self.tim.smcr.modify(|_, w| unsafe { w.ts().bits(MASTER::ITR) });
+            self.tim.smcr.modify(|_, w| unsafe { w.sms().bits(mode as u8) });
+        }
+    };
+}
+
+macro_rules! sync {
+    ($($TIM:ident: $max_mode:expr,)+) => {
+        $(
+            impl Timer<$TIM> {
+                master_mode!($max_mode);
+                slave_mode!($TIM);
+            }
+
+            impl MonoTimer<$TIM> {
+                master_mode!($max_mode);
+                slave_mode!($TIM);
+            }
+
+            impl<PINS, BKIN> Pwm<$TIM, PINS, BKIN> {
+                master_mode!($max_mode);
+                slave_mode!($TIM);
+            }
+        )+
+    };
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+trigger_sources! {
+    TIM1: [TIM3: 0b010, TIM17: 0b011],
+    TIM3: [TIM1: 0b000, TIM14: 0b011],
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+trigger_sources! {
+    TIM1: [TIM15: 0b000],
+    TIM3: [TIM15: 0b010],
+    TIM15: [TIM3: 0b001, TIM16: 0b010, TIM17: 0b011],
+}
+
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+sync! {
+    TIM1: MasterMode::Compare4,
+    TIM3: MasterMode::Compare4,
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030cc",
+    feature = "stm32f030r8",
+    feature = "stm32f030rc"
+))]
+sync! {
+    TIM15: MasterMode::Compare2,
+}
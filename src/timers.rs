@@ -1,66 +1,209 @@
-use stm32::{RCC, TIM1, TIM14, TIM15, TIM16, TIM17, TIM3, TIM6, TIM7};
+use stm32::{TIM1, TIM14, TIM15, TIM16, TIM17, TIM3, TIM6, TIM7};
 
-use cast::{u16, u32};
+use core::ops::{Add, Sub};
+
+use cast::u32;
 use hal::timer::{CountDown, Periodic};
 use nb;
 use rcc::Clocks;
 use void::Void;
 
-use time::Hertz;
+use time::{Hertz, KiloHertz, MegaHertz, MicroSeconds, MilliSeconds};
 
 /// Hardware timers
 pub struct Timer<TIM> {
     clocks: Clocks,
-    tim: TIM,
-    timeout: Hertz,
+    pub(crate) tim: TIM,
+    timeout: Timeout,
+}
+
+/// Hardware timer which counts down only once per start, see `CountDown`
+pub struct OneShotTimer<TIM> {
+    timer: Timer<TIM>,
+}
+
+/// Free running timer counting ticks at a fixed rate, extended to 64 bits in software
+///
+/// The counter overflows are counted by `on_overflow`, which has to be called from the timer
+/// interrupt or otherwise at least once per counter period, `now` does so too.
+pub struct MonoTimer<TIM> {
+    pub(crate) tim: TIM,
+    frequency: Hertz,
+    overflows: u64,
+}
+
+/// Point in time of a `MonoTimer`, in ticks since it was started
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    /// Returns the ticks since the timer was started
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Returns the ticks passed from `earlier` to this instant, or 0 if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> u64 {
+        self.ticks.saturating_sub(earlier.ticks)
+    }
+}
+
+impl Add<u64> for Instant {
+    type Output = Instant;
+
+    fn add(self, ticks: u64) -> Instant {
+        Instant {
+            ticks: self.ticks + ticks,
+        }
+    }
+}
+
+impl Sub<u64> for Instant {
+    type Output = Instant;
+
+    fn sub(self, ticks: u64) -> Instant {
+        Instant {
+            ticks: self.ticks - ticks,
+        }
+    }
+}
+
+/// Timeout of a count down, either as frequency or as duration
+#[derive(Clone, Copy)]
+pub enum Timeout {
+    Frequency(Hertz),
+    MilliSeconds(MilliSeconds),
+    MicroSeconds(MicroSeconds),
+}
+
+impl From<Hertz> for Timeout {
+    fn from(frequency: Hertz) -> Self {
+        Timeout::Frequency(frequency)
+    }
+}
+
+impl From<KiloHertz> for Timeout {
+    fn from(frequency: KiloHertz) -> Self {
+        Timeout::Frequency(frequency.into())
+    }
+}
+
+impl From<MegaHertz> for Timeout {
+    fn from(frequency: MegaHertz) -> Self {
+        Timeout::Frequency(frequency.into())
+    }
+}
+
+impl From<MilliSeconds> for Timeout {
+    fn from(duration: MilliSeconds) -> Self {
+        Timeout::MilliSeconds(duration)
+    }
+}
+
+impl From<MicroSeconds> for Timeout {
+    fn from(duration: MicroSeconds) -> Self {
+        Timeout::MicroSeconds(duration)
+    }
+}
+
+impl Timeout {
+    /// Returns the number of cycles of the timer clock `clk` in the timeout
+    pub(crate) fn ticks(self, clk: Hertz) -> Option<u64> {
+        let clk = u64::from(clk.0);
+        match self {
+            Timeout::Frequency(Hertz(0)) => None,
+            Timeout::Frequency(Hertz(f)) if u64::from(f) > clk => None,
+            Timeout::Frequency(Hertz(f)) => Some((clk + u64::from(f) / 2) / u64::from(f)),
+            Timeout::MilliSeconds(MilliSeconds(ms)) => Some(clk * u64::from(ms) / 1_000),
+            Timeout::MicroSeconds(MicroSeconds(us)) => Some(clk * u64::from(us) / 1_000_000),
+        }
+    }
+}
+
+/// Timer error
+#[derive(Debug)]
+pub enum Error {
+    /// The timeout can't be reached with the timer clock
+    InvalidTimeout,
+}
+
+/// Returns the prescaler and auto-reload values for a period of `ticks` timer clock cycles, with
+/// an auto-reload value of at most `max_arr`
+///
+/// The smallest possible prescaler is used, so the period is as close to `ticks` as possible.
+pub(crate) fn psc_arr(ticks: u64, max_arr: u16) -> Option<(u16, u16)> {
+    let max_period = u64::from(max_arr) + 1;
+
+    /* An auto-reload value of 0 stops the counter */
+    if ticks < 2 || ticks > max_period << 16 {
+        return None;
+    }
+
+    let psc = (ticks - 1) / max_period;
+    let arr = (ticks + psc / 2) / (psc + 1) - 1;
+
+    Some((psc as u16, arr as u16))
 }
 
 /// Interrupt events
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Event {
     /// Timer timed out / count down ended
     TimeOut,
+    /// Capture or compare on channel 1
+    CaptureCompare1,
+    /// Capture or compare on channel 2
+    CaptureCompare2,
+    /// Capture or compare on channel 3
+    CaptureCompare3,
+    /// Capture or compare on channel 4
+    CaptureCompare4,
+    /// Trigger input of the slave mode controller
+    Trigger,
+    /// Break input
+    Break,
+}
+
+impl Event {
+    /// Returns the bit of the event in DIER and SR
+    fn mask(self) -> u32 {
+        match self {
+            Event::TimeOut => 1 << 0,
+            Event::CaptureCompare1 => 1 << 1,
+            Event::CaptureCompare2 => 1 << 2,
+            Event::CaptureCompare3 => 1 << 3,
+            Event::CaptureCompare4 => 1 << 4,
+            Event::Trigger => 1 << 6,
+            Event::Break => 1 << 7,
+        }
+    }
 }
 
 macro_rules! timers {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident, $events:expr),)+) => {
         $(
             impl Periodic for Timer<$TIM> {}
 
             impl CountDown for Timer<$TIM> {
-                type Time = Hertz;
+                type Time = Timeout;
 
-                // NOTE(allow) `w.psc().bits()` is safe for TIM{6,7} but not for TIM{2,3,4} due to
-                // some SVD omission
-                #[allow(unused_unsafe)]
+                /// Starts a new count down
+                ///
+                /// Panics if the timeout can't be reached, see `try_start`.
                 fn start<T>(&mut self, timeout: T)
                 where
-                    T: Into<Hertz>,
+                    T: Into<Timeout>,
                 {
-                    // pause
-                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
-                    // restart counter
-                    self.tim.cnt.reset();
-
-                    self.timeout = timeout.into();
-
-                    let frequency = self.timeout.0;
-                    let ticks = self.clocks.pclk().0 / frequency;
-
-                    let psc = u16((ticks - 1) / (1 << 16)).unwrap();
-                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
-
-                    let arr = u16(ticks / u32(psc + 1)).unwrap();
-                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
-
-                    // start counter
-                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+                    self.try_start(timeout).expect("timeout out of range");
                 }
 
                 fn wait(&mut self) -> nb::Result<(), Void> {
                     if self.tim.sr.read().uif().bit_is_clear() {
                         Err(nb::Error::WouldBlock)
                     } else {
-                        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                        self.clear_interrupt(Event::TimeOut);
                         Ok(())
                     }
                 }
@@ -73,45 +216,167 @@ macro_rules! timers {
                 /// Configures a TIM peripheral as a periodic count down timer
                 pub fn $tim<T>(tim: $TIM, timeout: T, clocks: Clocks) -> Self
                 where
-                    T: Into<Hertz>,
+                    T: Into<Timeout>,
                 {
-                    // NOTE(unsafe) This executes only during initialisation
-                    let rcc = unsafe { &(*RCC::ptr()) };
-                    // enable and reset peripheral to a clean slate state
-                    rcc.$apbenr.modify(|_, w| w.$timXen().set_bit());
-                    rcc.$apbrstr.modify(|_, w| w.$timXrst().set_bit());
-                    rcc.$apbrstr.modify(|_, w| w.$timXrst().clear_bit());
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
 
                     let mut timer = Timer {
                         clocks,
                         tim,
-                        timeout: Hertz(0),
+                        timeout: Timeout::Frequency(Hertz(0)),
                     };
                     timer.start(timeout);
 
                     timer
                 }
 
+                /// Starts a new count down, or fails if the timeout is shorter than two or longer
+                /// than 2^32 timer clock cycles
+                // NOTE(allow) `w.psc().bits()` is safe for TIM{6,7} but not for TIM{2,3,4} due to
+                // some SVD omission
+                #[allow(unused_unsafe)]
+                pub fn try_start<T>(&mut self, timeout: T) -> Result<(), Error>
+                where
+                    T: Into<Timeout>,
+                {
+                    let timeout = timeout.into();
+                    let (psc, arr) = timeout
+                        .ticks(self.clocks.timclk())
+                        .and_then(|ticks| psc_arr(ticks, 0xFFFF))
+                        .ok_or(Error::InvalidTimeout)?;
+
+                    // pause
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    // restart counter
+                    self.tim.cnt.reset();
+
+                    self.timeout = timeout;
+
+                    self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
+
+                    // load the prescaler right away, without flagging a timeout
+                    self.tim.cr1.modify(|_, w| w.urs().set_bit());
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                    self.clear_interrupt(Event::TimeOut);
+
+                    // start counter
+                    self.tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    Ok(())
+                }
+
+                /// Returns the DIER and SR bit of `event`, panics if the timer doesn't have it
+                fn event_mask(event: Event) -> u32 {
+                    let mask = event.mask();
+                    assert!(mask & $events != 0, "timer doesn't have this event");
+                    mask
+                }
+
                 /// Starts listening for an `event`
                 pub fn listen(&mut self, event: Event) {
-                    match event {
-                        Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier.write(|w| w.uie().set_bit());
-                        }
-                    }
+                    let mask = Self::event_mask(event);
+                    self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
                 }
 
                 /// Stops listening for an `event`
                 pub fn unlisten(&mut self, event: Event) {
-                    match event {
-                        Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier.write(|w| w.uie().clear_bit());
-                        }
+                    let mask = Self::event_mask(event);
+                    self.tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+                }
+
+                /// Returns whether `event` occurred since its flag was last cleared
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.tim.sr.read().bits() & Self::event_mask(event) != 0
+                }
+
+                /// Clears the flag of `event`
+                pub fn clear_interrupt(&mut self, event: Event) {
+                    let mask = Self::event_mask(event);
+                    // the flags are cleared by writing 0, writing 1 has no effect
+                    self.tim.sr.write(|w| unsafe { w.bits(0xFFFF & !mask) });
+                }
+
+                /// Releases the TIM peripheral
+                pub fn free(self) -> $TIM {
+                    // pause counter
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+            }
+
+            impl MonoTimer<$TIM> {
+                /// Configures a TIM peripheral as free running timer counting at `frequency`
+                ///
+                /// The frequency is rounded to the nearest possible one, see `frequency`.
+                // NOTE(allow) `w.psc().bits()` is safe for TIM{6,7} but not for TIM{2,3,4} due to
+                // some SVD omission
+                #[allow(unused_unsafe)]
+                pub fn $tim<F>(tim: $TIM, frequency: F, clocks: Clocks) -> Self
+                where
+                    F: Into<Hertz>,
+                {
+                    let clk = clocks.timclk().0;
+                    let frequency = frequency.into().0;
+                    assert!(frequency > 0 && frequency <= clk);
+                    let psc = (clk + frequency / 2) / frequency - 1;
+                    assert!(psc <= 0xFFFF, "frequency too low");
+                    let psc = psc as u16;
+
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    tim.psc.write(|w| unsafe { w.psc().bits(psc) });
+                    tim.arr.write(|w| unsafe { w.bits(0xFFFF) });
+
+                    // load the prescaler right away, only counter overflows flag an update
+                    tim.cr1.modify(|_, w| w.urs().set_bit());
+                    tim.egr.write(|w| w.ug().set_bit());
+                    // the flags are cleared by writing 0, writing 1 has no effect
+                    tim.sr.write(|w| unsafe { w.bits(0xFFFF & !0x0001) });
+                    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+                    MonoTimer {
+                        tim,
+                        frequency: Hertz(clk / (u32(psc) + 1)),
+                        overflows: 0,
+                    }
+                }
+
+                /// Returns the rate the timer counts at
+                pub fn frequency(&self) -> Hertz {
+                    self.frequency
+                }
+
+                overflow_counter!();
+
+                /// Returns the current instant
+                pub fn now(&mut self) -> Instant {
+                    Instant {
+                        ticks: self.extended_count(),
                     }
                 }
 
+                /// Returns the ticks passed since `earlier`
+                pub fn elapsed(&mut self, earlier: Instant) -> u64 {
+                    self.now().duration_since(earlier)
+                }
+
+                /// Converts a number of `ticks` into microseconds
+                pub fn to_us(&self, ticks: u64) -> u64 {
+                    let frequency = u64::from(self.frequency.0);
+                    ticks / frequency * 1_000_000 + ticks % frequency * 1_000_000 / frequency
+                }
+
+                /// Enables the overflow interrupt, whose handler has to call `on_overflow`
+                pub fn listen(&mut self) {
+                    self.tim.dier.modify(|_, w| w.uie().set_bit());
+                }
+
+                /// Disables the overflow interrupt
+                pub fn unlisten(&mut self) {
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                }
+
                 /// Releases the TIM peripheral
                 pub fn free(self) -> $TIM {
                     // pause counter
@@ -123,6 +388,94 @@ macro_rules! timers {
     }
 }
 
+macro_rules! one_shot_timers {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl CountDown for OneShotTimer<$TIM> {
+                type Time = Timeout;
+
+                /// Starts a new count down
+                ///
+                /// Panics if the timeout can't be reached, see `try_start`.
+                fn start<T>(&mut self, timeout: T)
+                where
+                    T: Into<Timeout>,
+                {
+                    self.timer.start(timeout);
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    self.timer.wait()
+                }
+            }
+
+            impl OneShotTimer<$TIM> {
+                /// Configures a TIM peripheral as a one-shot count down timer
+                ///
+                /// The timer doesn't run until it's started.
+                pub fn $tim(tim: $TIM, clocks: Clocks) -> Self {
+                    enable_reset!($apbenr, $apbrstr, $timXen, $timXrst);
+
+                    // stop counting at the next update event
+                    tim.cr1.modify(|_, w| w.opm().set_bit());
+
+                    OneShotTimer {
+                        timer: Timer {
+                            clocks,
+                            tim,
+                            timeout: Timeout::Frequency(Hertz(0)),
+                        },
+                    }
+                }
+
+                /// Starts a new count down, or fails if the timeout is shorter than two or longer
+                /// than 2^32 timer clock cycles
+                pub fn try_start<T>(&mut self, timeout: T) -> Result<(), Error>
+                where
+                    T: Into<Timeout>,
+                {
+                    self.timer.try_start(timeout)
+                }
+
+                /// Returns whether a count down is in progress
+                pub fn is_running(&self) -> bool {
+                    self.timer.tim.cr1.read().cen().bit_is_set()
+                }
+
+                /// Stops the count down in progress
+                pub fn cancel(&mut self) {
+                    self.timer.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                }
+
+                /// Starts listening for an `event`
+                pub fn listen(&mut self, event: Event) {
+                    self.timer.listen(event)
+                }
+
+                /// Stops listening for an `event`
+                pub fn unlisten(&mut self, event: Event) {
+                    self.timer.unlisten(event)
+                }
+
+                /// Returns whether `event` occurred since its flag was last cleared
+                pub fn is_pending(&self, event: Event) -> bool {
+                    self.timer.is_pending(event)
+                }
+
+                /// Clears the flag of `event`
+                pub fn clear_interrupt(&mut self, event: Event) {
+                    self.timer.clear_interrupt(event)
+                }
+
+                /// Releases the TIM peripheral
+                pub fn free(self) -> $TIM {
+                    self.timer.free()
+                }
+            }
+        )+
+    }
+}
+
 #[cfg(any(
     feature = "stm32f030f4",
     feature = "stm32f030k6",
@@ -133,9 +486,43 @@ macro_rules! timers {
     feature = "stm32f030rc"
 ))]
 timers! {
+    // The last field has the DIER and SR bits of the events each timer supports
+    TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr, 0x00DF),
+    TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr, 0x005F),
+    TIM14: (tim14, tim14en, tim14rst, apb1enr, apb1rstr, 0x0003),
+    TIM16: (tim16, tim16en, tim16rst, apb2enr, apb2rstr, 0x00C3),
+    TIM17: (tim17, tim17en, tim17rst, apb2enr, apb2rstr, 0x00C3),
+}
+
+#[cfg(any(
+    feature = "stm32f030c8",
+    feature = "stm32f030r8",
+    feature = "stm32f030cc",
+    feature = "stm32f030rc"
+))]
+timers! {
+    TIM6: (tim6, tim6en, tim6rst, apb1enr, apb1rstr, 0x0001),
+    TIM15: (tim15, tim15en, tim15rst, apb2enr, apb2rstr, 0x00C7),
+}
+
+#[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
+timers! {
+    TIM7: (tim7, tim7en, tim7rst, apb1enr, apb1rstr, 0x0001),
+}
+
+// TIM14 has no one-pulse mode
+#[cfg(any(
+    feature = "stm32f030f4",
+    feature = "stm32f030k6",
+    feature = "stm32f030c6",
+    feature = "stm32f030c8",
+    feature = "stm32f030r8",
+    feature = "stm32f030cc",
+    feature = "stm32f030rc"
+))]
+one_shot_timers! {
     TIM1: (tim1, tim1en, tim1rst, apb2enr, apb2rstr),
     TIM3: (tim3, tim3en, tim3rst, apb1enr, apb1rstr),
-    TIM14: (tim14, tim14en, tim14rst, apb1enr, apb1rstr),
     TIM16: (tim16, tim16en, tim16rst, apb2enr, apb2rstr),
     TIM17: (tim17, tim17en, tim17rst, apb2enr, apb2rstr),
 }
@@ -146,12 +533,12 @@ timers! {
     feature = "stm32f030cc",
     feature = "stm32f030rc"
 ))]
-timers! {
+one_shot_timers! {
     TIM6: (tim6, tim6en, tim6rst, apb1enr, apb1rstr),
     TIM15: (tim15, tim15en, tim15rst, apb2enr, apb2rstr),
 }
 
 #[cfg(any(feature = "stm32f030cc", feature = "stm32f030rc"))]
-timers! {
+one_shot_timers! {
     TIM7: (tim7, tim7en, tim7rst, apb1enr, apb1rstr),
 }